@@ -0,0 +1,119 @@
+use crate::error::Error;
+use jsonwebtoken::Algorithm;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The signing algorithms the tool can encode and decode tokens with
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SupportedAlgorithms {
+    HS256,
+    HS384,
+    HS512,
+    RS256,
+    RS384,
+    RS512,
+    PS256,
+    PS384,
+    PS512,
+    ES256,
+    ES384,
+    ES512,
+    ES256K,
+    EdDSA,
+}
+
+impl SupportedAlgorithms {
+    pub fn variants() -> [&'static str; 14] {
+        [
+            "HS256", "HS384", "HS512", "RS256", "RS384", "RS512", "PS256", "PS384", "PS512",
+            "ES256", "ES384", "ES512", "ES256K", "EdDSA",
+        ]
+    }
+
+    /// Parses an algorithm name as it appears in the `alg` header
+    pub fn from_string(alg: &str) -> Result<SupportedAlgorithms, Error> {
+        match alg {
+            "HS256" => Ok(SupportedAlgorithms::HS256),
+            "HS384" => Ok(SupportedAlgorithms::HS384),
+            "HS512" => Ok(SupportedAlgorithms::HS512),
+            "RS256" => Ok(SupportedAlgorithms::RS256),
+            "RS384" => Ok(SupportedAlgorithms::RS384),
+            "RS512" => Ok(SupportedAlgorithms::RS512),
+            "PS256" => Ok(SupportedAlgorithms::PS256),
+            "PS384" => Ok(SupportedAlgorithms::PS384),
+            "PS512" => Ok(SupportedAlgorithms::PS512),
+            "ES256" => Ok(SupportedAlgorithms::ES256),
+            "ES384" => Ok(SupportedAlgorithms::ES384),
+            "ES512" => Ok(SupportedAlgorithms::ES512),
+            "ES256K" => Ok(SupportedAlgorithms::ES256K),
+            "EdDSA" => Ok(SupportedAlgorithms::EdDSA),
+            _ => Err(Error::UnsupportedAlgorithm(format!(
+                "{} isn't a supported algorithm",
+                alg
+            ))),
+        }
+    }
+
+    // The equivalent jsonwebtoken algorithm, for the ones it implements itself
+    pub(crate) fn jsonwebtoken_algorithm(self) -> Option<Algorithm> {
+        match self {
+            SupportedAlgorithms::HS256 => Some(Algorithm::HS256),
+            SupportedAlgorithms::HS384 => Some(Algorithm::HS384),
+            SupportedAlgorithms::HS512 => Some(Algorithm::HS512),
+            SupportedAlgorithms::RS256 => Some(Algorithm::RS256),
+            SupportedAlgorithms::RS384 => Some(Algorithm::RS384),
+            SupportedAlgorithms::RS512 => Some(Algorithm::RS512),
+            SupportedAlgorithms::PS256 => Some(Algorithm::PS256),
+            SupportedAlgorithms::PS384 => Some(Algorithm::PS384),
+            SupportedAlgorithms::PS512 => Some(Algorithm::PS512),
+            SupportedAlgorithms::ES256 => Some(Algorithm::ES256),
+            SupportedAlgorithms::ES384 => Some(Algorithm::ES384),
+            SupportedAlgorithms::EdDSA => Some(Algorithm::EdDSA),
+            SupportedAlgorithms::ES512 | SupportedAlgorithms::ES256K => None,
+        }
+    }
+}
+
+impl FromStr for SupportedAlgorithms {
+    type Err = Error;
+
+    fn from_str(alg: &str) -> Result<Self, Self::Err> {
+        SupportedAlgorithms::from_string(alg)
+    }
+}
+
+impl fmt::Display for SupportedAlgorithms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SupportedAlgorithms;
+    use jsonwebtoken::Algorithm;
+
+    #[test]
+    fn translates_algorithm() {
+        for (alg, expected) in &[
+            (SupportedAlgorithms::HS256, Some(Algorithm::HS256)),
+            (SupportedAlgorithms::HS384, Some(Algorithm::HS384)),
+            (SupportedAlgorithms::HS512, Some(Algorithm::HS512)),
+            (SupportedAlgorithms::RS256, Some(Algorithm::RS256)),
+            (SupportedAlgorithms::RS384, Some(Algorithm::RS384)),
+            (SupportedAlgorithms::RS512, Some(Algorithm::RS512)),
+            (SupportedAlgorithms::PS256, Some(Algorithm::PS256)),
+            (SupportedAlgorithms::PS384, Some(Algorithm::PS384)),
+            (SupportedAlgorithms::PS512, Some(Algorithm::PS512)),
+            (SupportedAlgorithms::ES256, Some(Algorithm::ES256)),
+            (SupportedAlgorithms::ES384, Some(Algorithm::ES384)),
+            (SupportedAlgorithms::EdDSA, Some(Algorithm::EdDSA)),
+            (SupportedAlgorithms::ES512, None),
+            (SupportedAlgorithms::ES256K, None),
+        ] {
+            assert_eq!(alg.jsonwebtoken_algorithm(), *expected);
+        }
+    }
+}
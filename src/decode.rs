@@ -0,0 +1,876 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result, ValidationError};
+use crate::jwe::{decrypt, insecure_decode_jwe_header, is_jwe, JweHeader};
+use crate::jwk::thumbprint;
+use crate::jws::{
+    attach_payload, decode, insecure_decode, insecure_decode_payload, is_detached,
+    is_json_serialization, split_json_serialization, verify_signature, Header, TokenData,
+};
+use crate::keys::{
+    candidate_jwks, decoding_keys_from_secret, read_secret, KeyFormat, VerificationKey,
+};
+use crate::payload::Payload;
+use crate::policy::{violation, Policy, PolicyViolation};
+use crate::remote::{fetch_jwks, JwksSource};
+use crate::validation::{validate, Validation};
+use crate::x509::{
+    certificate_jwk, certificate_key, decode_x5c, validate_cert_chain, ChainCertificate,
+};
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// The options used to verify a token. Without a secret, the signature isn't checked.
+/// The secret of an encrypted token is the key to decrypt it with instead.
+/// When the token is nested, the secret is only for its outermost layer, and the layers
+/// inside one which was decrypted or verified have to be verified too.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// The algorithm the token must be signed with. When not set, the `alg` in the
+    /// token header is used, as long as it suits the type of the secret.
+    pub algorithm: Option<SupportedAlgorithms>,
+    pub secret: Option<String>,
+    pub key_format: Option<KeyFormat>,
+    /// The secret the layers inside a nested token are decrypted or verified with. The keys
+    /// of a policy verify every layer instead, so it can't be given along with them.
+    pub inner_secret: Option<String>,
+    pub inner_key_format: Option<KeyFormat>,
+    /// Where to fetch a JWK Set to verify the token with, instead of using `secret`
+    pub jwks_source: Option<JwksSource>,
+    /// The directory fetched JWK Sets are cached in. Without one, they're fetched every time.
+    pub jwks_cache_dir: Option<PathBuf>,
+    pub ignore_exp: bool,
+    /// The seconds of clock skew allowed when checking `exp`, `nbf` and `iat`. Default: 1000.
+    pub leeway: Option<u64>,
+    /// The issuer the `iss` claim must be
+    pub expected_issuer: Option<String>,
+    /// The audiences the `aud` claim has to include at least one of
+    pub expected_audiences: Vec<String>,
+    /// The value the `sub` claim must have
+    pub expected_subject: Option<String>,
+    /// Claims which have to be present in the token
+    pub required_claims: Vec<String>,
+    /// Reject tokens whose `nbf` is in the future
+    pub validate_nbf: bool,
+    /// Reject tokens issued, according to `iat`, more than this many seconds ago
+    pub max_age: Option<u64>,
+    /// A policy the token is checked against as well, which can also say where its keys are.
+    /// Tokens fail it when there are no keys to verify them with.
+    pub policy: Option<Policy>,
+    /// The payload of a token which was signed with it detached (`header..signature`)
+    pub detached_payload: Option<Vec<u8>>,
+    /// DER certificates the `x5c` chain in the token header, or in its JWK, has to lead
+    /// to. With them, the token is verified with the key of the chain's leaf certificate.
+    pub trust_anchors: Vec<Vec<u8>>,
+    /// Show the NumericDates of the date claims as RFC 3339 dates
+    pub iso_dates: bool,
+    /// Claims holding a NumericDate, besides `exp`, `nbf`, `iat`, `auth_time` and `updated_at`
+    pub date_claims: Vec<String>,
+}
+
+/// The result of decoding a token
+#[derive(Debug)]
+pub struct DecodeOutcome {
+    /// The token after its signature and claims have been validated. Its header is the
+    /// one of the JWE when the claims are only encrypted.
+    pub validated: Result<TokenData<Payload, LayerHeader>>,
+    /// The token as it was decoded, without any validation
+    pub token_data: Result<TokenData<Payload, LayerHeader>>,
+    /// The kid, or thumbprint, of the JWK whose signature matched
+    pub verified_with: Option<String>,
+    /// How many keys the signature was checked against
+    pub keys_tried: usize,
+    /// The rules of the policy the token breaks
+    pub policy_violations: Vec<PolicyViolation>,
+    /// The headers of every layer of a nested token, from the outermost to the one with
+    /// the claims. Empty unless the token is nested.
+    pub layers: Vec<LayerHeader>,
+    /// The index in `layers` of the layer which couldn't be decrypted or verified
+    pub invalid_layer: Option<usize>,
+    /// How each signature of a token in the JSON serialization was checked. Empty for
+    /// compact tokens.
+    pub signatures: Vec<SignatureResult>,
+    /// The certificate chain the token was verified with, from its leaf to the trust
+    /// anchor. Empty unless there are trust anchors.
+    pub cert_chain: Vec<ChainCertificate>,
+    /// Whether a key verified the signature, even if the claims then failed validation
+    pub signature_valid: bool,
+    /// The algorithm the signature was checked with
+    pub algorithm: Option<SupportedAlgorithms>,
+    /// The claims which were validated once the signature was verified
+    pub claims_checked: Vec<String>,
+}
+
+impl DecodeOutcome {
+    /// Sums up how the token was verified, and every reason it isn't valid
+    pub fn verification(&self) -> Verification {
+        let error = |code: &str, message: String| VerificationError {
+            code: code.to_string(),
+            message,
+        };
+        let errors = match &self.validated {
+            _ if !self.policy_violations.is_empty() => self
+                .policy_violations
+                .iter()
+                .map(|violation| error("policy_violation", violation.to_string()))
+                .collect(),
+            Err(err) => vec![error(err.code(), err.to_string())],
+            Ok(_) => vec![],
+        };
+
+        Verification {
+            signature_valid: self.signature_valid,
+            algorithm: self.algorithm,
+            key_id_used: self.verified_with.clone(),
+            claims_checked: self.claims_checked.clone(),
+            errors,
+        }
+    }
+}
+
+/// How a token was verified, as reported by `--json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Verification {
+    /// Whether a key verified the signature. Without any keys, none is.
+    pub signature_valid: bool,
+    pub algorithm: Option<SupportedAlgorithms>,
+    /// The kid, or thumbprint, of the JWK whose signature matched
+    pub key_id_used: Option<String>,
+    pub claims_checked: Vec<String>,
+    pub errors: Vec<VerificationError>,
+}
+
+/// One reason a token isn't valid. The code is one of those of [`Error::code`], or
+/// `policy_violation` for each rule of a policy the token breaks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerificationError {
+    pub code: String,
+    pub message: String,
+}
+
+/// How long a token has left and how old it is, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timing {
+    /// Negative once the token has expired
+    pub seconds_until_exp: Option<i64>,
+    pub seconds_since_iat: Option<i64>,
+}
+
+impl Timing {
+    /// Works out the timing of a token from its `exp` and `iat` claims, which can be
+    /// UNIX timestamps or RFC 3339 dates, at the UNIX timestamp `now`
+    pub fn new(claims: &Payload, now: i64) -> Timing {
+        let timestamp = |claim: &str| match claims.0.get(claim) {
+            Some(Value::String(date)) => DateTime::parse_from_rfc3339(date)
+                .ok()
+                .map(|date| date.timestamp()),
+            Some(value) => value.as_i64(),
+            None => None,
+        };
+
+        Timing {
+            seconds_until_exp: timestamp("exp").map(|exp| exp - now),
+            seconds_since_iat: timestamp("iat").map(|iat| now - iat),
+        }
+    }
+}
+
+/// How one signature of a token in the JSON serialization was checked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignatureResult {
+    /// The protected header of the signature
+    pub header: Header,
+    /// The header parameters which aren't protected by the signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unprotected: Option<Value>,
+    /// Whether a key verified the signature. Without any keys, none are verified.
+    pub verified: bool,
+    /// The kid, or thumbprint, of the JWK which verified the signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_with: Option<String>,
+    /// Why the signature, or the claims, couldn't be validated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The header of one layer of a nested token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LayerHeader {
+    Encrypted(JweHeader),
+    Signed(Header),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TokenOutput {
+    pub header: LayerHeader,
+    pub payload: Payload,
+    /// The headers of every layer of a nested token, from the outermost one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<LayerHeader>,
+    /// How each signature of a token in the JSON serialization was checked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<SignatureResult>,
+    /// The certificate chain the token was verified with, from its leaf to the trust anchor
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cert_chain: Vec<ChainCertificate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+}
+
+impl TokenOutput {
+    pub fn new(data: TokenData<Payload, LayerHeader>) -> Self {
+        TokenOutput {
+            header: data.header,
+            payload: data.claims,
+            layers: vec![],
+            signatures: vec![],
+            cert_chain: vec![],
+            verification: None,
+            timing: None,
+        }
+    }
+}
+
+// The secret a token is verified with: the JWK Set `options` say where to fetch, the
+// secret they have or else the one in their policy
+fn resolve_secret(options: &DecodeOptions) -> Result<Option<(String, Option<KeyFormat>)>> {
+    let policy = options.policy.as_ref();
+    let policy_has_keys = policy.is_some_and(|p| p.secret().is_some() || p.jwks_source().is_some());
+    let keys_given =
+        options.jwks_source.is_some() || options.secret.as_deref().is_some_and(|s| !s.is_empty());
+
+    // Keys given as well would otherwise quietly stand in for those the policy trusts
+    if policy_has_keys && keys_given {
+        return Err(Error::Policy(vec![violation(
+            "keys",
+            "the policy says which keys to trust, so no others can be given".to_string(),
+        )]));
+    }
+
+    let jwks_source = options
+        .jwks_source
+        .clone()
+        .or_else(|| policy.and_then(Policy::jwks_source));
+    let fetched_jwks = match jwks_source {
+        Some(source) => Some(serde_json::to_string(&fetch_jwks(
+            &source,
+            options.jwks_cache_dir.as_deref(),
+        )?)?),
+        None => None,
+    };
+
+    let (secret, key_format) = match (&fetched_jwks, options.secret.as_deref()) {
+        (Some(set), _) => (Some(set.clone()), Some(KeyFormat::JWK)),
+        (None, Some(secret)) if !secret.is_empty() => {
+            (Some(secret.to_string()), options.key_format)
+        }
+        (None, _) => match policy.and_then(Policy::secret) {
+            Some((secret, key_format)) => (Some(secret), options.key_format.or(key_format)),
+            None => (None, None),
+        },
+    };
+
+    Ok(secret
+        .filter(|s| !s.is_empty())
+        .map(|secret| (secret, key_format)))
+}
+
+// The keys `options` lead to which could verify a token signed with `algorithm`, or None
+// when there aren't any and the token is only decoded. A policy can't vouch for a token
+// whose signature isn't checked, so it needs keys.
+fn verification_keys(
+    options: &DecodeOptions,
+    algorithm: SupportedAlgorithms,
+    kid: Option<&String>,
+) -> Result<Option<Vec<VerificationKey>>> {
+    match resolve_secret(options)? {
+        Some((secret, key_format)) => {
+            decoding_keys_from_secret(&algorithm, &secret, key_format, kid).map(Some)
+        }
+        None if options.policy.is_some() => Err(Error::Policy(vec![violation(
+            "keys",
+            "the policy needs keys or a secret".to_string(),
+        )])),
+        None => Ok(None),
+    }
+}
+
+// Why a layer inside one which was decrypted or verified isn't trusted without a key
+fn unverified_inner_layer() -> Error {
+    Error::Crypto(
+        "the token inside is signed, so it needs a key to be verified with too".to_string(),
+    )
+}
+
+// A token inside a layer which was decrypted or verified, which is only trusted once it's
+// verified too. Its claims are still validated, so that the most telling error is reported.
+fn unverified_inner_token(jwt: &str, validation: &Validation) -> Result<TokenData<Payload>> {
+    let token = insecure_decode(jwt)?;
+
+    validate(&token.claims, validation)?;
+
+    Err(unverified_inner_layer())
+}
+
+// Checks the signature of `jwt` against each key `options` lead to, returning the validated
+// token along with the key which verified it and how many keys were tried
+fn verify(
+    jwt: &str,
+    options: &DecodeOptions,
+    algorithm: SupportedAlgorithms,
+    kid: Option<&String>,
+    validation: &Validation,
+    needs_key: bool,
+) -> (Result<TokenData<Payload>>, Option<String>, usize) {
+    let keys = match verification_keys(options, algorithm, kid) {
+        Ok(Some(keys)) => keys,
+        Ok(None) if needs_key => return (unverified_inner_token(jwt, validation), None, 0),
+        Ok(None) => return (insecure_decode(jwt), None, 0),
+        Err(err) => return (Err(err), None, 0),
+    };
+
+    // Keys are tried until one's signature matches, as the claims are the same for all of them
+    let mut validated = Err(Error::InvalidSignature);
+    let mut keys_tried = 0;
+
+    for key in keys {
+        keys_tried += 1;
+        validated = decode(jwt, &key.key, validation);
+
+        match &validated {
+            Err(Error::InvalidSignature) => continue,
+            _ => return (validated, key.kid, keys_tried),
+        }
+    }
+
+    (validated, None, keys_tried)
+}
+
+// A chain of DER certificates, with the kid of the JWK it's from
+type KeyCertChain = (Option<String>, Vec<Vec<u8>>);
+
+// The certificate chains a token could be verified with: the one in its header, or else
+// those of the JWKs in the secret which suit it
+fn cert_chains(
+    header: &Header,
+    options: &DecodeOptions,
+    algorithm: SupportedAlgorithms,
+) -> Result<Vec<KeyCertChain>> {
+    if let Some(x5c) = &header.x5c {
+        return Ok(vec![(None, decode_x5c(x5c)?)]);
+    }
+
+    let (secret, format) = match resolve_secret(options)? {
+        Some((secret, format)) => read_secret(&secret, format)?,
+        None => return Ok(vec![]),
+    };
+
+    if format != KeyFormat::JWK {
+        return Ok(vec![]);
+    }
+
+    candidate_jwks(algorithm, &secret, header.kid.as_ref())?
+        .into_iter()
+        .filter_map(|jwk| {
+            let x5c: Vec<String> = serde_json::from_value(jwk["x5c"].clone()).ok()?;
+
+            Some((jwk["kid"].as_str().map(String::from), x5c))
+        })
+        .map(|(kid, x5c)| Ok((kid, decode_x5c(&x5c)?)))
+        .collect()
+}
+
+// Verifies `jwt` with the leaf certificate of a chain which leads to one of the trust anchors,
+// returning the chain as well
+fn verify_cert_chain(
+    jwt: &str,
+    header: &Header,
+    options: &DecodeOptions,
+    algorithm: SupportedAlgorithms,
+    validation: &Validation,
+) -> (
+    Result<TokenData<Payload>>,
+    Option<String>,
+    usize,
+    Vec<ChainCertificate>,
+) {
+    let chains = match cert_chains(header, options, algorithm) {
+        Ok(chains) if !chains.is_empty() => chains,
+        Ok(_) => {
+            let err = Error::Crypto(
+                "neither the token header nor its key has a certificate chain to check against the trust anchors".to_string(),
+            );
+
+            return (Err(err), None, 0, vec![]);
+        }
+        Err(err) => return (Err(err), None, 0, vec![]),
+    };
+    let now = Utc::now().timestamp();
+    let mut validated = Err(Error::InvalidSignature);
+    let mut keys_tried = 0;
+
+    for (kid, chain) in chains {
+        keys_tried += 1;
+
+        let path = match validate_cert_chain(&chain, &options.trust_anchors, now) {
+            Ok(path) => path,
+            Err(err) => {
+                validated = Err(err);
+                continue;
+            }
+        };
+
+        validated =
+            certificate_key(&chain[0], algorithm).and_then(|key| decode(jwt, &key, validation));
+
+        match &validated {
+            Err(Error::InvalidSignature) => continue,
+            _ => {
+                let kid = kid.or_else(|| {
+                    certificate_jwk(&chain[0])
+                        .and_then(|jwk| thumbprint(&jwk))
+                        .ok()
+                });
+
+                return (validated, kid, keys_tried, path);
+            }
+        }
+    }
+
+    (validated, None, keys_tried, vec![])
+}
+
+// Whether the signature of a token was verified, which it is before the claims are validated
+fn signature_verified<T>(validated: &Result<T>) -> bool {
+    match validated {
+        Ok(_) => true,
+        Err(Error::Validation(ValidationError::Algorithm)) => false,
+        Err(err) => matches!(err, Error::Validation(_)),
+    }
+}
+
+// Whether validation got as far as the rules a policy has, so that the policy can report on all of them
+fn policy_applies<T>(validated: &Result<T>) -> bool {
+    signature_verified(validated)
+        || matches!(
+            validated,
+            Err(Error::Validation(ValidationError::Algorithm))
+        )
+}
+
+// The options for the layers inside a nested token, which are decrypted or verified with
+// the inner secret instead
+fn inner_options(options: &DecodeOptions) -> DecodeOptions {
+    DecodeOptions {
+        secret: options.inner_secret.clone(),
+        key_format: options.inner_key_format,
+        ..options.clone()
+    }
+}
+
+// Adds the header of a layer around the token `outcome` is for, failing it if the layer did
+fn wrap_layer(
+    mut outcome: DecodeOutcome,
+    header: LayerHeader,
+    verified: (Result<()>, Option<String>, usize),
+) -> DecodeOutcome {
+    if outcome.layers.is_empty() {
+        if let Ok(token) = &outcome.token_data {
+            outcome.layers.push(token.header.clone());
+        }
+
+        if outcome.validated.is_err() {
+            outcome.invalid_layer = Some(0);
+        }
+    }
+
+    outcome.layers.insert(0, header);
+    outcome.invalid_layer = outcome.invalid_layer.map(|layer| layer + 1);
+
+    if let (Err(err), _, keys_tried) = verified {
+        outcome.validated = Err(err);
+        outcome.verified_with = None;
+        outcome.keys_tried = keys_tried;
+        outcome.signature_valid = false;
+        outcome.claims_checked = vec![];
+        outcome.invalid_layer = Some(0);
+    }
+
+    outcome
+}
+
+// Decrypts a JWE with the secret in `options`, then decodes the token or the claims inside it
+fn decode_encrypted_token(jwe: &str, options: &DecodeOptions) -> DecodeOutcome {
+    let decrypted = match options.secret.as_deref().filter(|s| !s.is_empty()) {
+        Some(secret) => decrypt(jwe, secret, options.key_format),
+        None => Err(Error::Crypto(
+            "the token is encrypted, so decoding it needs the key to decrypt it with".to_string(),
+        )),
+    };
+    let (header, plaintext) = match decrypted {
+        Ok(decrypted) => decrypted,
+        Err(err) => return undecryptable_token(jwe, err),
+    };
+
+    // Unless the header says it's a nested JWT, the plaintext can be the claims themselves
+    if let (false, Ok(claims)) = (header.is_nested(), serde_json::from_slice(&plaintext)) {
+        return decode_encrypted_claims(header, claims, options);
+    }
+
+    match String::from_utf8(plaintext) {
+        Ok(inner) if is_jwe(&inner) || inner.split('.').count() == 3 => wrap_layer(
+            decode_layer(&inner, &inner_options(options), true),
+            LayerHeader::Encrypted(header),
+            (Ok(()), None, 0),
+        ),
+        _ => undecryptable_token(
+            jwe,
+            Error::Malformed("the token encrypts neither a JWT nor its claims".to_string()),
+        ),
+    }
+}
+
+// A JWE which couldn't be decrypted, or had something other than a token or claims inside
+fn undecryptable_token(jwe: &str, err: Error) -> DecodeOutcome {
+    DecodeOutcome {
+        validated: Err(err),
+        token_data: Err(Error::not_a_jwt()),
+        verified_with: None,
+        keys_tried: 0,
+        policy_violations: vec![],
+        layers: insecure_decode_jwe_header(jwe)
+            .map(LayerHeader::Encrypted)
+            .into_iter()
+            .collect(),
+        invalid_layer: Some(0),
+        signatures: vec![],
+        cert_chain: vec![],
+        signature_valid: false,
+        algorithm: None,
+        claims_checked: vec![],
+    }
+}
+
+// Validates the claims a JWE encrypts directly, which were vouched for by decrypting them
+fn decode_encrypted_claims(
+    header: JweHeader,
+    claims: Payload,
+    options: &DecodeOptions,
+) -> DecodeOutcome {
+    let validation = validation(options, None);
+    let mut validated = validate(&claims, &validation);
+    let policy_violations = match &options.policy {
+        Some(policy) if policy_applies(&validated) => policy.check(None, &claims, &validation),
+        _ => vec![],
+    };
+
+    if !policy_violations.is_empty() && validated.is_ok() {
+        validated = Err(Error::Policy(policy_violations.clone()));
+    }
+
+    let mut token = TokenData {
+        header: LayerHeader::Encrypted(header),
+        claims,
+    };
+    let validated = validated.map(|_| token.clone());
+
+    if options.iso_dates {
+        token.claims.convert_timestamps(&options.date_claims);
+    }
+
+    DecodeOutcome {
+        validated,
+        token_data: Ok(token),
+        verified_with: None,
+        keys_tried: 0,
+        policy_violations,
+        layers: vec![],
+        invalid_layer: None,
+        signatures: vec![],
+        cert_chain: vec![],
+        signature_valid: false,
+        algorithm: None,
+        claims_checked: validation.checked_claims(),
+    }
+}
+
+// Checks the signature of a layer of a nested token with the keys `options` lead to, like
+// those of tokens themselves. Layers are only verified when there are keys.
+fn verify_layer(
+    jwt: &str,
+    header: &Header,
+    options: &DecodeOptions,
+    needs_key: bool,
+) -> (Result<()>, Option<String>, usize) {
+    let algorithm = options.algorithm.unwrap_or(header.alg);
+    let keys = match verification_keys(options, algorithm, header.kid.as_ref()) {
+        Ok(Some(keys)) => keys,
+        Ok(None) if needs_key => return (Err(unverified_inner_layer()), None, 0),
+        Ok(None) => return (Ok(()), None, 0),
+        Err(err) => return (Err(err), None, 0),
+    };
+    let mut verified = Err(Error::InvalidSignature);
+    let mut keys_tried = 0;
+
+    for key in keys {
+        keys_tried += 1;
+        verified = verify_signature(jwt, &key.key, &[algorithm]).map(|_| ());
+
+        match &verified {
+            Err(Error::InvalidSignature) => continue,
+            _ => return (verified, key.kid, keys_tried),
+        }
+    }
+
+    (verified, None, keys_tried)
+}
+
+// Verifies the outer layer of a signed token whose payload is another token, then decodes that
+fn decode_nested_token(
+    jwt: &str,
+    header: Header,
+    inner: &str,
+    options: &DecodeOptions,
+    needs_key: bool,
+) -> DecodeOutcome {
+    let verified = verify_layer(jwt, &header, options, needs_key);
+    let outer_verified = verified.0.is_ok() && verified.2 > 0;
+
+    wrap_layer(
+        decode_layer(inner, &inner_options(options), outer_verified),
+        LayerHeader::Signed(header),
+        verified,
+    )
+}
+
+// A token which can't be decoded at all
+fn invalid_token(err: Error) -> DecodeOutcome {
+    DecodeOutcome {
+        validated: Err(err),
+        token_data: Err(Error::not_a_jwt()),
+        verified_with: None,
+        keys_tried: 0,
+        policy_violations: vec![],
+        layers: vec![],
+        invalid_layer: None,
+        signatures: vec![],
+        cert_chain: vec![],
+        signature_valid: false,
+        algorithm: None,
+        claims_checked: vec![],
+    }
+}
+
+// Decodes each signature of a token in the JSON serialization as a compact token. The token
+// is valid when any of its signatures is, and the outcome is the first verified signature's.
+fn decode_json_token(token: &str, options: &DecodeOptions, needs_key: bool) -> DecodeOutcome {
+    let signatures = match split_json_serialization(token) {
+        Ok(signatures) if !signatures.is_empty() => signatures,
+        _ => return invalid_token(Error::not_a_jwt()),
+    };
+    let mut outcomes: Vec<DecodeOutcome> = signatures
+        .iter()
+        .map(|signature| decode_layer(&signature.compact, options, needs_key))
+        .collect();
+    let verified = |outcome: &DecodeOutcome| outcome.validated.is_ok() && outcome.keys_tried > 0;
+    let mut results = vec![];
+
+    for (signature, outcome) in signatures.into_iter().zip(&outcomes) {
+        let header = match insecure_decode_payload(&signature.compact) {
+            Ok((header, _)) => header,
+            Err(_) => return invalid_token(Error::not_a_jwt()),
+        };
+
+        results.push(SignatureResult {
+            header,
+            unprotected: signature.unprotected,
+            verified: verified(outcome),
+            verified_with: outcome.verified_with.clone(),
+            error: outcome.validated.as_ref().err().map(ToString::to_string),
+        });
+    }
+
+    let chosen = outcomes.iter().position(verified).unwrap_or(0);
+    let mut outcome = outcomes.swap_remove(chosen);
+
+    outcome.signatures = results;
+    outcome
+}
+
+/// Decodes `jwt`, validating it against the secret in `options` when one is given.
+/// Nested tokens are unwrapped layer by layer, decrypting or verifying the outermost
+/// one with the secret and the ones inside it with the inner secret. Tokens in the JSON
+/// serialization are valid when any of their signatures is.
+pub fn decode_token(jwt: &str, options: &DecodeOptions) -> DecodeOutcome {
+    decode_layer(jwt, options, false)
+}
+
+// Decodes a token, or a layer inside one. A layer inside one which was decrypted or
+// verified needs a key as well, as it could have been signed by anyone otherwise.
+fn decode_layer(jwt: &str, options: &DecodeOptions, needs_key: bool) -> DecodeOutcome {
+    let jwt = jwt.trim();
+
+    if is_json_serialization(jwt) {
+        return decode_json_token(jwt, options, needs_key);
+    }
+
+    if is_jwe(jwt) {
+        return decode_encrypted_token(jwt, options);
+    }
+
+    match (&options.detached_payload, is_detached(jwt)) {
+        (Some(payload), _) => {
+            return match attach_payload(jwt, payload) {
+                Ok(attached) => decode_signed_token(&attached, options, needs_key),
+                Err(err) => invalid_token(err),
+            }
+        }
+        (None, true) => {
+            return invalid_token(Error::Malformed(
+                "the payload of the token is detached, so it has to be given separately"
+                    .to_string(),
+            ))
+        }
+        _ => (),
+    }
+
+    if let Ok((header, payload)) = insecure_decode_payload(jwt) {
+        if header.is_nested() {
+            if let Ok(inner) = String::from_utf8(payload) {
+                return decode_nested_token(jwt, header, &inner, options, needs_key);
+            }
+        }
+    }
+
+    decode_signed_token(jwt, options, needs_key)
+}
+
+// What the claims of a token are validated against, which for one signed with `algorithm`
+// includes that it's the only one allowed. Claims which are only encrypted have no algorithm.
+fn validation(options: &DecodeOptions, algorithm: Option<SupportedAlgorithms>) -> Validation {
+    let mut validation = Validation::new(algorithm.unwrap_or(SupportedAlgorithms::HS256));
+
+    validation.leeway = 1000;
+
+    if let Some(policy) = &options.policy {
+        policy.apply(&mut validation);
+    }
+
+    if let Some(leeway) = options.leeway {
+        validation.leeway = leeway;
+    }
+
+    validation.validate_nbf |= options.validate_nbf;
+
+    if options.ignore_exp {
+        validation.validate_exp = false;
+        validation.required_spec_claims.remove("exp");
+    }
+
+    validation
+        .required_spec_claims
+        .extend(options.required_claims.iter().cloned());
+
+    if let Some(iss) = &options.expected_issuer {
+        validation.set_issuer(&[iss]);
+    }
+
+    if !options.expected_audiences.is_empty() {
+        validation.set_audience(&options.expected_audiences);
+    }
+
+    if let Some(sub) = &options.expected_subject {
+        validation.set_subject(sub);
+    }
+
+    if let Some(max_age) = options.max_age {
+        validation.set_max_age(max_age);
+    }
+
+    if algorithm.is_none() {
+        validation.algorithms.clear();
+    }
+
+    validation
+}
+
+// A signed token, with its header as one of the layers a token can have
+fn signed_token(token: TokenData<Payload>) -> TokenData<Payload, LayerHeader> {
+    TokenData {
+        header: LayerHeader::Signed(token.header),
+        claims: token.claims,
+    }
+}
+
+// Decodes a signed token whose payload is its claims
+fn decode_signed_token(jwt: &str, options: &DecodeOptions, needs_key: bool) -> DecodeOutcome {
+    let token_data = insecure_decode(jwt).map(|mut token| {
+        if options.iso_dates {
+            token.claims.convert_timestamps(&options.date_claims);
+        }
+
+        token
+    });
+
+    let (kid, header_alg) = match &token_data {
+        Ok(token) => (token.header.kid.as_ref(), Some(token.header.alg)),
+        _ => (None, None),
+    };
+
+    // A token whose header can't be read fails validation below regardless
+    let algorithm = options
+        .algorithm
+        .or(header_alg)
+        .unwrap_or(SupportedAlgorithms::HS256);
+
+    let secret_validator = validation(options, Some(algorithm));
+
+    let (mut validated, verified_with, keys_tried, cert_chain) = match &token_data {
+        Ok(token) if !options.trust_anchors.is_empty() => {
+            verify_cert_chain(jwt, &token.header, options, algorithm, &secret_validator)
+        }
+        _ => {
+            let (validated, verified_with, keys_tried) =
+                verify(jwt, options, algorithm, kid, &secret_validator, needs_key);
+
+            (validated, verified_with, keys_tried, vec![])
+        }
+    };
+
+    let signature_valid = keys_tried > 0 && signature_verified(&validated);
+    let claims_checked = match signature_valid {
+        true => secret_validator.checked_claims(),
+        false => vec![],
+    };
+
+    // The claims are decoded again, as `token_data` may have had its dates converted
+    let policy_violations = match (&options.policy, insecure_decode(jwt)) {
+        (Some(policy), Ok(token)) if policy_applies(&validated) => {
+            policy.check(Some(token.header.alg), &token.claims, &secret_validator)
+        }
+        _ => vec![],
+    };
+
+    if !policy_violations.is_empty() && validated.is_ok() {
+        validated = Err(Error::Policy(policy_violations.clone()));
+    }
+
+    DecodeOutcome {
+        validated: validated.map(signed_token),
+        token_data: token_data.map(signed_token),
+        verified_with,
+        keys_tried,
+        policy_violations,
+        layers: vec![],
+        invalid_layer: None,
+        signatures: vec![],
+        cert_chain,
+        signature_valid,
+        algorithm: header_alg.map(|_| algorithm),
+        claims_checked,
+    }
+}
@@ -0,0 +1,492 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result};
+use crate::jwe::{encrypt, ContentEncryptionAlgorithm, JweHeader, KeyManagementAlgorithm};
+use crate::jwk::{jwk_from_key, thumbprint};
+use crate::jws::{
+    detach_payload, encode, encode_json, sign_detached, sign_payload, Header, Serialization,
+};
+use crate::keys::{encoding_key_from_secret, KeyFormat};
+use crate::payload::{Payload, PayloadItem};
+use crate::x509::{add_cert_chain, certificate_jwk, read_cert_chain};
+use chrono::Utc;
+use serde_json::{from_str, Value};
+use std::collections::BTreeMap;
+
+/// Everything needed to build and sign a token. Construct it with
+/// [`EncodeOptions::new`] and chain the setters for the claims you need.
+#[derive(Debug)]
+pub struct EncodeOptions {
+    algorithm: SupportedAlgorithms,
+    kid: Option<String>,
+    typ: Option<String>,
+    cty: Option<String>,
+    header_params: Vec<PayloadItem>,
+    header_json: Option<String>,
+    cert_chain: Option<String>,
+    secret: String,
+    key_format: Option<KeyFormat>,
+    json: Option<String>,
+    claims: Vec<PayloadItem>,
+    expires: Option<String>,
+    not_before: Option<String>,
+    issued_at: bool,
+    issuer: Option<String>,
+    subject: Option<String>,
+    audience: Option<String>,
+    jwt_id: Option<String>,
+    encryption_key: Option<String>,
+    encryption_key_format: Option<KeyFormat>,
+    encryption_algorithm: Option<KeyManagementAlgorithm>,
+    content_encryption: ContentEncryptionAlgorithm,
+    other_signers: Vec<(String, SupportedAlgorithms, Option<String>)>,
+    serialization: Serialization,
+    detached: bool,
+    payload: Option<Vec<u8>>,
+}
+
+impl EncodeOptions {
+    /// Starts a new set of options signing with `secret` using HS256. The
+    /// secret can be prefixed with @ to read it from a file.
+    pub fn new(secret: &str) -> Self {
+        EncodeOptions {
+            algorithm: SupportedAlgorithms::HS256,
+            kid: None,
+            typ: None,
+            cty: None,
+            header_params: vec![],
+            header_json: None,
+            cert_chain: None,
+            secret: secret.to_string(),
+            key_format: None,
+            json: None,
+            claims: vec![],
+            expires: None,
+            not_before: None,
+            issued_at: true,
+            issuer: None,
+            subject: None,
+            audience: None,
+            jwt_id: None,
+            encryption_key: None,
+            encryption_key_format: None,
+            encryption_algorithm: None,
+            content_encryption: ContentEncryptionAlgorithm::A256GCM,
+            other_signers: vec![],
+            serialization: Serialization::Compact,
+            detached: false,
+            payload: None,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: SupportedAlgorithms) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn kid(mut self, kid: &str) -> Self {
+        self.kid = Some(kid.to_string());
+        self
+    }
+
+    /// The `typ` header parameter, such as `at+jwt`. Default: JWT
+    pub fn typ(mut self, typ: &str) -> Self {
+        self.typ = Some(typ.to_string());
+        self
+    }
+
+    /// The `cty` header parameter
+    pub fn cty(mut self, cty: &str) -> Self {
+        self.cty = Some(cty.to_string());
+        self
+    }
+
+    /// Adds a parameter to the header of every signature. `alg` can't be changed this way.
+    pub fn header_param(mut self, item: PayloadItem) -> Self {
+        self.header_params.push(item);
+        self
+    }
+
+    /// A JSON object whose fields are added to the header of every signature, after
+    /// the parameters added one at a time
+    pub fn header_json(mut self, json: &str) -> Self {
+        self.header_json = Some(json.to_string());
+        self
+    }
+
+    /// A PEM file with the certificate chain of the signing key, leaf first, to put
+    /// in the header as `x5c` along with the thumbprints of the leaf
+    pub fn cert_chain(mut self, path: &str) -> Self {
+        self.cert_chain = Some(path.to_string());
+        self
+    }
+
+    /// The format of the secret. When not set, it's guessed from the file extension.
+    pub fn key_format(mut self, format: KeyFormat) -> Self {
+        self.key_format = Some(format);
+        self
+    }
+
+    /// A JSON object whose fields are added to the claims
+    pub fn json(mut self, json: &str) -> Self {
+        self.json = Some(json.to_string());
+        self
+    }
+
+    /// Adds a claim. Its name can be a path to a nested claim, such as `user.roles[]`,
+    /// as [`Payload::insert_path`] takes.
+    pub fn claim(mut self, item: PayloadItem) -> Self {
+        self.claims.push(item);
+        self
+    }
+
+    /// When the token expires, as a UNIX timestamp or systemd.time string
+    pub fn expires(mut self, expires: &str) -> Self {
+        self.expires = Some(expires.to_string());
+        self
+    }
+
+    /// When the token becomes valid, as a UNIX timestamp or systemd.time string
+    pub fn not_before(mut self, not_before: &str) -> Self {
+        self.not_before = Some(not_before.to_string());
+        self
+    }
+
+    /// Prevents an `iat` claim from being added automatically
+    pub fn no_iat(mut self) -> Self {
+        self.issued_at = false;
+        self
+    }
+
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    pub fn jwt_id(mut self, jwt_id: &str) -> Self {
+        self.jwt_id = Some(jwt_id.to_string());
+        self
+    }
+
+    /// Encrypts the signed token for the holder of `key`, as a nested JWT. The key
+    /// can be prefixed with @ to read it from a file.
+    pub fn encrypt(mut self, key: &str) -> Self {
+        self.encryption_key = Some(key.to_string());
+        self
+    }
+
+    /// The format of the encryption key. When not set, it's guessed from the file extension.
+    pub fn encryption_key_format(mut self, format: KeyFormat) -> Self {
+        self.encryption_key_format = Some(format);
+        self
+    }
+
+    /// How the content encryption key is encrypted. When not set, it's picked by the
+    /// type of the encryption key.
+    pub fn encryption_algorithm(mut self, algorithm: KeyManagementAlgorithm) -> Self {
+        self.encryption_algorithm = Some(algorithm);
+        self
+    }
+
+    /// How the signed token is encrypted. Default: A256GCM
+    pub fn content_encryption(mut self, enc: ContentEncryptionAlgorithm) -> Self {
+        self.content_encryption = enc;
+        self
+    }
+
+    /// Signs the token with another secret as well, which only the general JSON
+    /// serialization can hold. The secret is read like the first one.
+    pub fn add_signer(
+        mut self,
+        secret: &str,
+        algorithm: SupportedAlgorithms,
+        kid: Option<&str>,
+    ) -> Self {
+        self.other_signers
+            .push((secret.to_string(), algorithm, kid.map(str::to_string)));
+        self
+    }
+
+    /// Leaves the payload out of the token, as `header..signature`, for it to be sent
+    /// separately (RFC 7515 appendix F)
+    pub fn detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+
+    /// Signs `payload` as it is instead of the claims, unencoded as RFC 7797 allows.
+    /// Unless the payload is detached, it can't have any dots.
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = Some(payload.to_vec());
+        self
+    }
+
+    /// How the signed token is serialized. Default: compact
+    pub fn serialization(mut self, serialization: Serialization) -> Self {
+        self.serialization = serialization;
+        self
+    }
+}
+
+pub fn create_header(alg: SupportedAlgorithms, kid: Option<&str>) -> Header {
+    let mut header = Header::new(alg);
+
+    header.kid = kid.map(str::to_string);
+
+    header
+}
+
+fn json_payload_items(raw_json: &str, name: &str) -> Result<Vec<PayloadItem>> {
+    let json = from_str(raw_json)
+        .map_err(|err| Error::Malformed(format!("the JSON {} is invalid: {}", name, err)))?;
+
+    match json {
+        Value::Object(json_value) => Ok(json_value
+            .into_iter()
+            .map(|(json_key, json_val)| PayloadItem(json_key, json_val))
+            .collect()),
+        _ => Err(Error::Malformed(format!(
+            "the JSON {} must be an object",
+            name
+        ))),
+    }
+}
+
+// Sets the header parameters given in `options` on top of the ones every token gets
+fn customize_header(header: Header, options: &EncodeOptions) -> Result<Header> {
+    let mut params = match serde_json::to_value(&header)? {
+        Value::Object(params) => params,
+        _ => unreachable!("headers serialize to objects"),
+    };
+    let custom_params = match &options.header_json {
+        Some(raw_json) => json_payload_items(raw_json, "header")?,
+        None => vec![],
+    };
+
+    if let Some(typ) = &options.typ {
+        params.insert("typ".to_string(), typ.clone().into());
+    }
+    if let Some(cty) = &options.cty {
+        params.insert("cty".to_string(), cty.clone().into());
+    }
+    for PayloadItem(name, value) in options.header_params.iter().cloned().chain(custom_params) {
+        params.insert(name, value);
+    }
+
+    if params.get("alg") != Some(&serde_json::to_value(header.alg)?) {
+        return Err(Error::Malformed(
+            "the alg header parameter can only be set with the algorithm".to_string(),
+        ));
+    }
+
+    Ok(serde_json::from_value(Value::Object(params))?)
+}
+
+// Wraps a signed token in a JWE whose `cty` says it's a nested JWT
+fn encrypt_token(jwt: &str, key: &str, options: &EncodeOptions) -> Result<String> {
+    let alg = match options.encryption_algorithm {
+        Some(alg) => alg,
+        None => KeyManagementAlgorithm::for_key(key, options.encryption_key_format)?,
+    };
+    let mut header = JweHeader::new(alg, options.content_encryption);
+
+    header.cty = Some("JWT".to_string());
+
+    encrypt(jwt.as_bytes(), &header, key, options.encryption_key_format)
+}
+
+// Whether `options` set any claims, besides the `iat` every token gets by default
+fn has_claims(options: &EncodeOptions) -> bool {
+    options.json.is_some()
+        || !options.claims.is_empty()
+        || options.expires.is_some()
+        || options.not_before.is_some()
+        || options.issuer.is_some()
+        || options.subject.is_some()
+        || options.audience.is_some()
+        || options.jwt_id.is_some()
+}
+
+// Signs a payload as it is, with the `b64` header parameter that says so
+fn encode_unencoded_payload(
+    mut header: Header,
+    payload: &[u8],
+    options: &EncodeOptions,
+) -> Result<String> {
+    if options.serialization != Serialization::Compact
+        || !options.other_signers.is_empty()
+        || options.encryption_key.is_some()
+    {
+        return Err(Error::Malformed(
+            "unencoded payloads can only be signed once, in the compact serialization".to_string(),
+        ));
+    }
+
+    if !options.detached && payload.contains(&b'.') {
+        return Err(Error::Malformed(
+            "unencoded payloads with dots have to be detached".to_string(),
+        ));
+    }
+
+    header.b64 = Some(false);
+    header.crit = Some(vec!["b64".to_string()]);
+
+    let secret = encoding_key_from_secret(&options.algorithm, &options.secret, options.key_format)?;
+
+    match options.detached {
+        true => Ok(sign_detached(&header, payload, &secret)?),
+        false => Ok(sign_payload(&header, payload, &secret)?),
+    }
+}
+
+// Adds the certificate chain in `options` to the header, once it's sure the leaf
+// certificate is for the signing key
+fn embed_cert_chain(header: &mut Header, path: &str, options: &EncodeOptions) -> Result<()> {
+    let chain = read_cert_chain(path)?;
+    let leaf = thumbprint(&certificate_jwk(&chain[0])?)?;
+    let signing_key = jwk_from_key(&options.secret, options.key_format)
+        .and_then(|jwk| thumbprint(&jwk))
+        .map_err(|_| {
+            Error::UnsupportedAlgorithm(
+                "only public key algorithms can have a certificate chain".to_string(),
+            )
+        })?;
+
+    if leaf != signing_key {
+        return Err(Error::Crypto(
+            "the first certificate in the chain isn't for the signing key".to_string(),
+        ));
+    }
+
+    add_cert_chain(header, &chain);
+
+    Ok(())
+}
+
+// Signs the claims with every signer and serializes them as JSON
+fn encode_json_token(
+    header: &Header,
+    claims: &BTreeMap<String, Value>,
+    options: &EncodeOptions,
+) -> Result<String> {
+    if options.encryption_key.is_some() {
+        return Err(Error::Malformed(
+            "encrypted tokens can only use the compact serialization".to_string(),
+        ));
+    }
+
+    let mut signers = vec![(
+        header.clone(),
+        encoding_key_from_secret(&options.algorithm, &options.secret, options.key_format)?,
+    )];
+
+    for (secret, algorithm, kid) in &options.other_signers {
+        signers.push((
+            customize_header(create_header(*algorithm, kid.as_deref()), options)?,
+            encoding_key_from_secret(algorithm, secret, options.key_format)?,
+        ));
+    }
+
+    let signers: Vec<_> = signers.iter().map(|(header, key)| (header, key)).collect();
+
+    encode_json(&signers, claims, options.serialization)
+}
+
+/// Builds the claims described by `options` and signs them, encrypting the
+/// signed token too when an encryption key is set
+pub fn encode_token(options: &EncodeOptions) -> Result<String> {
+    let mut header = customize_header(
+        create_header(options.algorithm, options.kid.as_deref()),
+        options,
+    )?;
+
+    if let Some(path) = &options.cert_chain {
+        embed_cert_chain(&mut header, path, options)?;
+    }
+
+    if header.b64 == Some(false) && options.payload.is_none() {
+        return Err(Error::Malformed(
+            "b64 can only be false when signing a payload file, which is then left unencoded"
+                .to_string(),
+        ));
+    }
+
+    let custom_payload = match &options.json {
+        Some(raw_json) => json_payload_items(raw_json, "payload")?,
+        None => vec![],
+    };
+    let now = Utc::now().timestamp();
+    let expires = PayloadItem::from_timestamp_with_name(options.expires.as_deref(), "exp", now);
+    let not_before =
+        PayloadItem::from_timestamp_with_name(options.not_before.as_deref(), "nbf", now);
+    let issued_at = match options.issued_at {
+        true => PayloadItem::from_timestamp_with_name(Some(&now.to_string()), "iat", now),
+        false => None,
+    };
+    let issuer = PayloadItem::from_string_with_name(options.issuer.as_deref(), "iss");
+    let subject = PayloadItem::from_string_with_name(options.subject.as_deref(), "sub");
+    let audience = PayloadItem::from_string_with_name(options.audience.as_deref(), "aud");
+    let jwt_id = PayloadItem::from_string_with_name(options.jwt_id.as_deref(), "jti");
+    let payloads: Vec<PayloadItem> = vec![
+        issued_at, expires, issuer, subject, audience, jwt_id, not_before,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut payload = Payload::from_payloads(payloads);
+
+    for PayloadItem(path, value) in &options.claims {
+        payload
+            .insert_path(path, value.clone())
+            .map_err(Error::Malformed)?;
+    }
+
+    let Payload(mut claims) = payload;
+
+    claims.extend(
+        custom_payload
+            .into_iter()
+            .map(|PayloadItem(name, value)| (name, value)),
+    );
+
+    if let Some(payload) = &options.payload {
+        if has_claims(options) {
+            return Err(Error::Malformed(
+                "a payload file is signed as it is, so claims can't be added to it".to_string(),
+            ));
+        }
+
+        return encode_unencoded_payload(header, payload, options);
+    }
+
+    if options.serialization != Serialization::Compact {
+        return encode_json_token(&header, &claims, options);
+    }
+
+    if !options.other_signers.is_empty() {
+        return Err(Error::Malformed(
+            "only the general JSON serialization can have more than one signature".to_string(),
+        ));
+    }
+
+    let secret = encoding_key_from_secret(&options.algorithm, &options.secret, options.key_format)?;
+    let jwt = encode(&header, &claims, &secret)?;
+    let jwt = match options.detached {
+        true => detach_payload(&jwt)?,
+        false => jwt,
+    };
+
+    match &options.encryption_key {
+        Some(key) => Ok(encrypt_token(&jwt, key, options)?),
+        None => Ok(jwt),
+    }
+}
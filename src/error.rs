@@ -0,0 +1,247 @@
+use crate::keys::KeyFormat;
+use crate::policy::PolicyViolation;
+use jsonwebtoken::errors::ErrorKind;
+use std::{fmt, io};
+
+/// Why a key couldn't be loaded, or a token encoded, decoded or validated. Each kind
+/// of error has its own exit code, so scripts can tell them apart:
+///
+/// | Code | Error                  |
+/// |------|------------------------|
+/// | 1    | `Malformed`            |
+/// | 2    | `Io`                   |
+/// | 3    | `KeyParse`             |
+/// | 4    | `KidNotFound`          |
+/// | 5    | `UnsupportedAlgorithm` |
+/// | 7    | `InvalidSignature`     |
+/// | 8    | `Crypto`               |
+/// | 9    | `Validation`           |
+/// | 10   | `Policy`               |
+///
+/// 6 is left to the `jwt` command, for a `decode --query` path with nothing at it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The token, or what it's built from, isn't well-formed
+    Malformed(String),
+    /// A file, or a JWK Set to fetch, couldn't be read
+    Io(String),
+    /// The secret isn't a valid key in its format
+    KeyParse { format: KeyFormat, reason: String },
+    /// The token has a kid that none of the keys in the JWK Set has
+    KidNotFound(String),
+    /// The key isn't meant for the algorithm, or the algorithm isn't known
+    UnsupportedAlgorithm(String),
+    /// The signature doesn't match the key
+    InvalidSignature,
+    /// A key couldn't be generated, or used to sign, encrypt or decrypt, or a certificate
+    /// chain can't be trusted
+    Crypto(String),
+    /// The claims of the token, or the algorithm it's signed with, aren't accepted
+    Validation(ValidationError),
+    /// The token breaks rules of a policy
+    Policy(Vec<PolicyViolation>),
+}
+
+/// Why a token whose signature is valid still isn't
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// The token is signed with an algorithm which isn't allowed
+    Algorithm,
+    Expired,
+    /// The token was issued longer ago than the maximum age, in seconds
+    TooOld(u64),
+    NotYetValid,
+    Issuer,
+    Audience,
+    Subject,
+    /// The claim is required but isn't set
+    MissingClaim(String),
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The code the `jwt` command exits with for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Malformed(_) => 1,
+            Error::Io(_) => 2,
+            Error::KeyParse { .. } => 3,
+            Error::KidNotFound(_) => 4,
+            Error::UnsupportedAlgorithm(_) => 5,
+            Error::InvalidSignature => 7,
+            Error::Crypto(_) => 8,
+            Error::Validation(_) => 9,
+            Error::Policy(_) => 10,
+        }
+    }
+
+    /// A short code for the error, like `expired` or `kid_not_found`, which stays the same
+    /// whatever the message is
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Malformed(_) => "invalid_token",
+            Error::Io(_) => "io",
+            Error::KeyParse { .. } => "key_parse",
+            Error::KidNotFound(_) => "kid_not_found",
+            Error::UnsupportedAlgorithm(_) => "unsupported_algorithm",
+            Error::InvalidSignature => "invalid_signature",
+            Error::Crypto(_) => "crypto",
+            Error::Validation(err) => err.code(),
+            Error::Policy(_) => "policy_violation",
+        }
+    }
+
+    // Classifies an error from loading a key in `format`
+    pub(crate) fn key(format: KeyFormat, err: jsonwebtoken::errors::Error) -> Error {
+        let reason = match err.kind() {
+            ErrorKind::InvalidAlgorithm | ErrorKind::InvalidAlgorithmName => {
+                return Error::UnsupportedAlgorithm(
+                    "the key isn't meant for the algorithm".to_string(),
+                )
+            }
+            ErrorKind::Json(json) if json.is_io() => return Error::Io(json.to_string()),
+            ErrorKind::InvalidKeyFormat => "it isn't a key of the expected type".to_string(),
+            ErrorKind::InvalidRsaKey(reason) => format!("it isn't a valid RSA key ({})", reason),
+            ErrorKind::InvalidEcdsaKey => "it isn't a valid EC key for the curve".to_string(),
+            ErrorKind::Json(json) => format!("it isn't valid JSON ({})", json),
+            ErrorKind::Base64(err) => format!("it has invalid base64 ({})", err),
+            ErrorKind::Utf8(_) => "it isn't valid UTF-8".to_string(),
+            _ => err.to_string(),
+        };
+
+        Error::KeyParse { format, reason }
+    }
+
+    // A token which isn't a JWT at all
+    pub(crate) fn not_a_jwt() -> Error {
+        Error::Malformed("the token isn't a JWT".to_string())
+    }
+}
+
+impl ValidationError {
+    /// The code of [`Error::code`] for this failure
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::Algorithm => "invalid_algorithm",
+            ValidationError::Expired => "expired",
+            ValidationError::TooOld(_) => "too_old",
+            ValidationError::NotYetValid => "not_yet_valid",
+            ValidationError::Issuer => "invalid_issuer",
+            ValidationError::Audience => "invalid_audience",
+            ValidationError::Subject => "invalid_subject",
+            ValidationError::MissingClaim(_) => "missing_claim",
+        }
+    }
+}
+
+// A number of seconds in the largest unit it's a whole number of, like `30m`. `is_multiple_of`
+// would need Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+fn duration(seconds: u64) -> String {
+    match seconds {
+        0 => "0s".to_string(),
+        _ if seconds % 86400 == 0 => format!("{}d", seconds / 86400),
+        _ if seconds % 3600 == 0 => format!("{}h", seconds / 3600),
+        _ if seconds % 60 == 0 => format!("{}m", seconds / 60),
+        _ => format!("{}s", seconds),
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Algorithm => write!(f, "the token is signed with another algorithm"),
+            ValidationError::Expired => write!(f, "the token has expired"),
+            ValidationError::TooOld(max_age) => write!(
+                f,
+                "the token was issued more than {} ago",
+                duration(*max_age)
+            ),
+            ValidationError::NotYetValid => write!(f, "the token isn't valid yet"),
+            ValidationError::Issuer => write!(f, "the issuer isn't the expected one"),
+            ValidationError::Audience => write!(f, "the audience isn't one of the expected ones"),
+            ValidationError::Subject => write!(f, "the subject isn't the expected one"),
+            ValidationError::MissingClaim(claim) => {
+                write!(f, "the {} claim is required but isn't set", claim)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Malformed(message) | Error::Io(message) | Error::Crypto(message) => {
+                write!(f, "{}", message)
+            }
+            Error::KeyParse { format, reason } => {
+                write!(f, "the {:?} key couldn't be parsed: {}", format, reason)
+            }
+            Error::KidNotFound(kid) => write!(f, "no key in the JWK Set has the kid {}", kid),
+            Error::UnsupportedAlgorithm(message) => write!(f, "{}", message),
+            Error::InvalidSignature => write!(f, "the signature doesn't match"),
+            Error::Validation(err) => write!(f, "{}", err),
+            Error::Policy(violations) => write!(
+                f,
+                "the token breaks {} rule(s) of the policy",
+                violations.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ValidationError> for Error {
+    fn from(err: ValidationError) -> Self {
+        Error::Validation(err)
+    }
+}
+
+// jsonwebtoken still signs and verifies with most keys, and checks they're valid
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        match err.kind() {
+            ErrorKind::InvalidSignature => Error::InvalidSignature,
+            ErrorKind::InvalidAlgorithm | ErrorKind::InvalidAlgorithmName => {
+                Error::UnsupportedAlgorithm("the key isn't meant for the algorithm".to_string())
+            }
+            ErrorKind::InvalidToken => Error::not_a_jwt(),
+            ErrorKind::Json(json) if json.is_io() => Error::Io(json.to_string()),
+            ErrorKind::Json(_) | ErrorKind::Base64(_) | ErrorKind::Utf8(_) => {
+                Error::Malformed(err.to_string())
+            }
+            ErrorKind::ExpiredSignature => Error::Validation(ValidationError::Expired),
+            ErrorKind::ImmatureSignature => Error::Validation(ValidationError::NotYetValid),
+            ErrorKind::InvalidIssuer => Error::Validation(ValidationError::Issuer),
+            ErrorKind::InvalidAudience => Error::Validation(ValidationError::Audience),
+            ErrorKind::InvalidSubject => Error::Validation(ValidationError::Subject),
+            ErrorKind::MissingRequiredClaim(claim) => {
+                Error::Validation(ValidationError::MissingClaim(claim.clone()))
+            }
+            _ => Error::Crypto(err.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        match err.is_io() {
+            true => Error::Io(err.to_string()),
+            false => Error::Malformed(err.to_string()),
+        }
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(err: base64::DecodeError) -> Self {
+        Error::Malformed(format!("invalid base64 ({})", err))
+    }
+}
@@ -0,0 +1,806 @@
+use crate::error::{Error, Result};
+use crate::jwk::ec_parameters;
+use crate::keys::{ec_public_key, ec_secret_key, read_secret, KeyFormat, KeyType};
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, Payload as AeadPayload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit, Nonce};
+use aes_kw::{KekAes128, KekAes192, KekAes256};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use elliptic_curve::ecdh::diffie_hellman;
+use elliptic_curve::pkcs8::AssociatedOid;
+use elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use elliptic_curve::{AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey};
+use hmac::{Hmac, Mac};
+use p256::NistP256;
+use p384::NistP384;
+use p521::NistP521;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{BigUint, Oaep, RsaPrivateKey, RsaPublicKey};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::{self, FromStr};
+
+/// The algorithms which encrypt, or agree on, the content encryption key of a JWE
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyManagementAlgorithm {
+    #[serde(rename = "RSA-OAEP")]
+    RsaOaep,
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+    #[serde(rename = "ECDH-ES")]
+    EcdhEs,
+    #[serde(rename = "ECDH-ES+A128KW")]
+    EcdhEsA128Kw,
+    #[serde(rename = "ECDH-ES+A192KW")]
+    EcdhEsA192Kw,
+    #[serde(rename = "ECDH-ES+A256KW")]
+    EcdhEsA256Kw,
+    A128KW,
+    A192KW,
+    A256KW,
+    #[serde(rename = "dir")]
+    Dir,
+}
+
+impl KeyManagementAlgorithm {
+    pub fn variants() -> [&'static str; 10] {
+        [
+            "RSA-OAEP",
+            "RSA-OAEP-256",
+            "ECDH-ES",
+            "ECDH-ES+A128KW",
+            "ECDH-ES+A192KW",
+            "ECDH-ES+A256KW",
+            "A128KW",
+            "A192KW",
+            "A256KW",
+            "dir",
+        ]
+    }
+
+    /// Parses an algorithm name as it appears in the `alg` header of a JWE
+    pub fn from_string(alg: &str) -> Result<KeyManagementAlgorithm> {
+        serde_json::from_value(Value::String(alg.to_string())).map_err(|_| {
+            Error::UnsupportedAlgorithm(format!("{} isn't a supported algorithm", alg))
+        })
+    }
+
+    // The length of the key wrapping key, for the algorithms which wrap the content key
+    fn key_wrap_length(self) -> Option<usize> {
+        match self {
+            KeyManagementAlgorithm::A128KW | KeyManagementAlgorithm::EcdhEsA128Kw => Some(16),
+            KeyManagementAlgorithm::A192KW | KeyManagementAlgorithm::EcdhEsA192Kw => Some(24),
+            KeyManagementAlgorithm::A256KW | KeyManagementAlgorithm::EcdhEsA256Kw => Some(32),
+            _ => None,
+        }
+    }
+
+    /// Picks the algorithm for a key, when it's obvious from its type: RSA-OAEP-256 for
+    /// RSA keys, ECDH-ES for EC keys, and A128KW, A192KW or A256KW for symmetric keys of
+    /// those lengths.
+    pub fn for_key(secret_string: &str, format: Option<KeyFormat>) -> Result<Self> {
+        let (secret, format) = read_secret(secret_string, format)?;
+
+        match KeyType::from_secret(&secret, format) {
+            Some(KeyType::Rsa) => Ok(KeyManagementAlgorithm::RsaOaep256),
+            Some(KeyType::Ec("P-256" | "P-384" | "P-521")) => Ok(KeyManagementAlgorithm::EcdhEs),
+            Some(KeyType::Symmetric) => match symmetric_key(&secret, format)?.len() {
+                16 => Ok(KeyManagementAlgorithm::A128KW),
+                24 => Ok(KeyManagementAlgorithm::A192KW),
+                32 => Ok(KeyManagementAlgorithm::A256KW),
+                _ => Err(no_algorithm()),
+            },
+            _ => Err(no_algorithm()),
+        }
+    }
+}
+
+impl FromStr for KeyManagementAlgorithm {
+    type Err = Error;
+
+    fn from_str(alg: &str) -> Result<Self> {
+        KeyManagementAlgorithm::from_string(alg)
+    }
+}
+
+impl fmt::Display for KeyManagementAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(Value::String(name)) => write!(f, "{}", name),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+/// The algorithms which encrypt the content of a JWE
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContentEncryptionAlgorithm {
+    A128GCM,
+    A256GCM,
+    #[serde(rename = "A128CBC-HS256")]
+    A128CBC_HS256,
+}
+
+impl ContentEncryptionAlgorithm {
+    pub fn variants() -> [&'static str; 3] {
+        ["A128GCM", "A256GCM", "A128CBC-HS256"]
+    }
+
+    /// Parses an algorithm name as it appears in the `enc` header of a JWE
+    pub fn from_string(enc: &str) -> Result<ContentEncryptionAlgorithm> {
+        serde_json::from_value(Value::String(enc.to_string())).map_err(|_| {
+            Error::UnsupportedAlgorithm(format!("{} isn't a supported algorithm", enc))
+        })
+    }
+
+    fn key_length(self) -> usize {
+        match self {
+            ContentEncryptionAlgorithm::A128GCM => 16,
+            ContentEncryptionAlgorithm::A256GCM | ContentEncryptionAlgorithm::A128CBC_HS256 => 32,
+        }
+    }
+
+    fn iv_length(self) -> usize {
+        match self {
+            ContentEncryptionAlgorithm::A128GCM | ContentEncryptionAlgorithm::A256GCM => 12,
+            ContentEncryptionAlgorithm::A128CBC_HS256 => 16,
+        }
+    }
+}
+
+impl FromStr for ContentEncryptionAlgorithm {
+    type Err = Error;
+
+    fn from_str(enc: &str) -> Result<Self> {
+        ContentEncryptionAlgorithm::from_string(enc)
+    }
+}
+
+impl fmt::Display for ContentEncryptionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(Value::String(name)) => write!(f, "{}", name),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+/// The protected header of a JWE. Only `alg` and `enc` are required.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JweHeader {
+    pub alg: KeyManagementAlgorithm,
+    pub enc: ContentEncryptionAlgorithm,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// The ephemeral public key of ECDH-ES algorithms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epk: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apu: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apv: Option<String>,
+    /// Compression isn't supported, so tokens which use it are refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zip: Option<String>,
+}
+
+impl JweHeader {
+    /// A header for a token encrypted with `alg` and `enc`
+    pub fn new(alg: KeyManagementAlgorithm, enc: ContentEncryptionAlgorithm) -> Self {
+        JweHeader {
+            alg,
+            enc,
+            typ: None,
+            cty: None,
+            kid: None,
+            epk: None,
+            apu: None,
+            apv: None,
+            zip: None,
+        }
+    }
+
+    /// Whether the content of the token is itself a JWT
+    pub fn is_nested(&self) -> bool {
+        self.cty
+            .as_deref()
+            .is_some_and(|cty| cty.eq_ignore_ascii_case("JWT"))
+    }
+}
+
+/// Whether a token is a JWE in its compact serialization, whose protected header has an
+/// `enc` or an `alg` which encrypts the content key. Counting its parts isn't enough, as the
+/// unencoded payload of a JWS can have dots too.
+pub fn is_jwe(token: &str) -> bool {
+    let header = token
+        .trim()
+        .split('.')
+        .next()
+        .and_then(|protected| b64_decode(protected).ok())
+        .and_then(|header| serde_json::from_slice::<Value>(&header).ok());
+
+    header.is_some_and(|header| {
+        header.get("enc").is_some()
+            || header["alg"]
+                .as_str()
+                .is_some_and(|alg| KeyManagementAlgorithm::from_string(alg).is_ok())
+    })
+}
+
+fn decryption_error() -> Error {
+    Error::Crypto("the token couldn't be decrypted with the key provided".to_string())
+}
+
+fn no_algorithm() -> Error {
+    Error::UnsupportedAlgorithm("no key management algorithm suits the key".to_string())
+}
+
+fn invalid_key(format: KeyFormat, reason: &str) -> Error {
+    Error::KeyParse {
+        format,
+        reason: reason.to_string(),
+    }
+}
+
+fn b64_decode(part: &str) -> Result<Vec<u8>> {
+    Ok(URL_SAFE_NO_PAD.decode(part)?)
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// The bytes of a symmetric key, which is either the secret itself or the `k` of an oct JWK
+fn symmetric_key(secret: &[u8], format: KeyFormat) -> Result<Vec<u8>> {
+    match format {
+        KeyFormat::JWK => {
+            let jwk: Value = serde_json::from_slice(secret)?;
+
+            match (&jwk["kty"], &jwk["k"]) {
+                (Value::String(kty), Value::String(k)) if kty == "oct" => b64_decode(k),
+                _ => Err(invalid_key(format, "it isn't a symmetric key")),
+            }
+        }
+        KeyFormat::PEM => Ok(secret.to_vec()),
+        KeyFormat::DER => Err(invalid_key(format, "it isn't a symmetric key")),
+    }
+}
+
+// Reads an RSA key in any format, with its private half when the secret has it
+fn rsa_keys(secret: &[u8], format: KeyFormat) -> Result<(RsaPublicKey, Option<RsaPrivateKey>)> {
+    let private_key = match format {
+        KeyFormat::PEM => str::from_utf8(secret).ok().and_then(|pem| {
+            RsaPrivateKey::from_pkcs1_pem(pem)
+                .ok()
+                .or_else(|| RsaPrivateKey::from_pkcs8_pem(pem).ok())
+        }),
+        KeyFormat::DER => RsaPrivateKey::from_pkcs1_der(secret)
+            .ok()
+            .or_else(|| RsaPrivateKey::from_pkcs8_der(secret).ok()),
+        KeyFormat::JWK => {
+            let jwk: Value = serde_json::from_slice(secret)?;
+            let int = |name: &str| {
+                jwk[name]
+                    .as_str()
+                    .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+                    .map(|bytes| BigUint::from_bytes_be(&bytes))
+            };
+
+            match (int("n"), int("e"), int("d"), int("p"), int("q")) {
+                (Some(n), Some(e), Some(d), Some(p), Some(q)) => {
+                    RsaPrivateKey::from_components(n, e, d, vec![p, q]).ok()
+                }
+                (Some(n), Some(e), None, _, _) => {
+                    return RsaPublicKey::new(n, e)
+                        .map(|key| (key, None))
+                        .map_err(|_| invalid_key(format, "it isn't a valid RSA key"))
+                }
+                _ => None,
+            }
+        }
+    };
+
+    if let Some(key) = private_key {
+        return Ok((key.to_public_key(), Some(key)));
+    }
+
+    let public_key = match format {
+        KeyFormat::PEM => str::from_utf8(secret).ok().and_then(|pem| {
+            RsaPublicKey::from_pkcs1_pem(pem)
+                .ok()
+                .or_else(|| RsaPublicKey::from_public_key_pem(pem).ok())
+        }),
+        _ => RsaPublicKey::from_pkcs1_der(secret)
+            .ok()
+            .or_else(|| RsaPublicKey::from_public_key_der(secret).ok()),
+    };
+
+    public_key
+        .map(|key| (key, None))
+        .ok_or_else(|| invalid_key(format, "it isn't a valid RSA key"))
+}
+
+// The Concat KDF of NIST SP 800-56A, which ECDH-ES derives keys with (RFC 7518 4.6.2)
+fn concat_kdf(z: &[u8], algorithm_id: &str, apu: &[u8], apv: &[u8], length: usize) -> Vec<u8> {
+    let mut other_info = vec![];
+
+    for part in [algorithm_id.as_bytes(), apu, apv] {
+        other_info.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        other_info.extend_from_slice(part);
+    }
+
+    other_info.extend_from_slice(&((length * 8) as u32).to_be_bytes());
+
+    let mut key = vec![];
+    let mut counter: u32 = 1;
+
+    while key.len() < length {
+        let mut hasher = Sha256::new();
+
+        hasher.update(counter.to_be_bytes());
+        hasher.update(z);
+        hasher.update(&other_info);
+        key.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+
+    key.truncate(length);
+    key
+}
+
+// Agrees on a shared secret with the recipient's public key, returning it with the ephemeral public key
+fn ecdh_sender<C>(secret: &[u8], format: KeyFormat, curve: &str) -> Result<(Vec<u8>, Value)>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let recipient =
+        ec_public_key::<C>(secret, format, curve).map_err(|err| Error::key(format, err))?;
+    let ephemeral = SecretKey::<C>::random(&mut OsRng);
+    let shared = diffie_hellman(ephemeral.to_nonzero_scalar(), recipient.as_affine());
+    let epk = ec_parameters(&ephemeral.public_key(), None, curve)?;
+
+    Ok((shared.raw_secret_bytes().to_vec(), Value::Object(epk)))
+}
+
+// Recovers the shared secret the sender agreed on from their ephemeral public key
+fn ecdh_recipient<C>(secret: &[u8], format: KeyFormat, curve: &str, epk: &Value) -> Result<Vec<u8>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let key = ec_secret_key::<C>(secret, format, curve).map_err(|err| Error::key(format, err))?;
+    let ephemeral: PublicKey<C> = ec_public_key(&serde_json::to_vec(epk)?, KeyFormat::JWK, curve)?;
+    let shared = diffie_hellman(key.to_nonzero_scalar(), ephemeral.as_affine());
+
+    Ok(shared.raw_secret_bytes().to_vec())
+}
+
+fn wrong_length() -> Error {
+    Error::Crypto("the key isn't the length the algorithm needs".to_string())
+}
+
+fn wrap_key(kek: &[u8], cek: &[u8]) -> Result<Vec<u8>> {
+    let wrapped = match kek.len() {
+        16 => KekAes128::try_from(kek).and_then(|kek| kek.wrap_vec(cek)),
+        24 => KekAes192::try_from(kek).and_then(|kek| kek.wrap_vec(cek)),
+        32 => KekAes256::try_from(kek).and_then(|kek| kek.wrap_vec(cek)),
+        _ => return Err(wrong_length()),
+    };
+
+    wrapped.map_err(|_| Error::Crypto("the content key couldn't be wrapped".to_string()))
+}
+
+fn unwrap_key(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>> {
+    let cek = match kek.len() {
+        16 => KekAes128::try_from(kek).and_then(|kek| kek.unwrap_vec(wrapped)),
+        24 => KekAes192::try_from(kek).and_then(|kek| kek.unwrap_vec(wrapped)),
+        32 => KekAes256::try_from(kek).and_then(|kek| kek.unwrap_vec(wrapped)),
+        _ => return Err(wrong_length()),
+    };
+
+    cek.map_err(|_| decryption_error())
+}
+
+// Makes sure a key suits the key management algorithm it's used with
+fn check_key_type(alg: KeyManagementAlgorithm, key_type: Option<KeyType>) -> Result<()> {
+    let suits = match alg {
+        KeyManagementAlgorithm::RsaOaep | KeyManagementAlgorithm::RsaOaep256 => {
+            key_type == Some(KeyType::Rsa)
+        }
+        KeyManagementAlgorithm::EcdhEs
+        | KeyManagementAlgorithm::EcdhEsA128Kw
+        | KeyManagementAlgorithm::EcdhEsA192Kw
+        | KeyManagementAlgorithm::EcdhEsA256Kw => matches!(
+            key_type,
+            Some(KeyType::Ec("P-256")) | Some(KeyType::Ec("P-384")) | Some(KeyType::Ec("P-521"))
+        ),
+        KeyManagementAlgorithm::A128KW
+        | KeyManagementAlgorithm::A192KW
+        | KeyManagementAlgorithm::A256KW
+        | KeyManagementAlgorithm::Dir => key_type == Some(KeyType::Symmetric),
+    };
+
+    if suits {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedAlgorithm(format!(
+            "the key isn't meant for {}",
+            alg
+        )))
+    }
+}
+
+// Works out the content encryption key for a new token, returning it along with the
+// encrypted key and the header parameters the recipient needs to work it out too
+fn content_key(
+    header: &mut JweHeader,
+    secret: &[u8],
+    format: KeyFormat,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key_type = KeyType::from_secret(secret, format);
+    let cek_length = header.enc.key_length();
+
+    check_key_type(header.alg, key_type)?;
+
+    match header.alg {
+        KeyManagementAlgorithm::Dir => {
+            let key = symmetric_key(secret, format)?;
+
+            if key.len() != cek_length {
+                return Err(wrong_length());
+            }
+
+            Ok((key, vec![]))
+        }
+        KeyManagementAlgorithm::A128KW
+        | KeyManagementAlgorithm::A192KW
+        | KeyManagementAlgorithm::A256KW => {
+            let kek = symmetric_key(secret, format)?;
+
+            if Some(kek.len()) != header.alg.key_wrap_length() {
+                return Err(wrong_length());
+            }
+
+            let cek = random_bytes(cek_length);
+            let wrapped = wrap_key(&kek, &cek)?;
+
+            Ok((cek, wrapped))
+        }
+        KeyManagementAlgorithm::RsaOaep | KeyManagementAlgorithm::RsaOaep256 => {
+            let (public_key, _) = rsa_keys(secret, format)?;
+            let cek = random_bytes(cek_length);
+            let padding = match header.alg {
+                KeyManagementAlgorithm::RsaOaep => Oaep::new::<sha1::Sha1>(),
+                _ => Oaep::new::<Sha256>(),
+            };
+            let encrypted = public_key
+                .encrypt(&mut OsRng, padding, &cek)
+                .map_err(|_| Error::Crypto("the content key couldn't be encrypted".to_string()))?;
+
+            Ok((cek, encrypted))
+        }
+        _ => {
+            let (z, epk) = match key_type {
+                Some(KeyType::Ec("P-256")) => ecdh_sender::<NistP256>(secret, format, "P-256")?,
+                Some(KeyType::Ec("P-384")) => ecdh_sender::<NistP384>(secret, format, "P-384")?,
+                _ => ecdh_sender::<NistP521>(secret, format, "P-521")?,
+            };
+
+            header.epk = Some(epk);
+            ecdh_content_key(header, &z, None)
+        }
+    }
+}
+
+// Derives the content encryption key from an ECDH-ES shared secret. The key is either the
+// derived key itself, or wrapped by it, in which case `wrapped` is unwrapped when decrypting
+// and a new key is wrapped when encrypting.
+fn ecdh_content_key(
+    header: &JweHeader,
+    z: &[u8],
+    wrapped: Option<&[u8]>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let apu = b64_decode(header.apu.as_deref().unwrap_or(""))?;
+    let apv = b64_decode(header.apv.as_deref().unwrap_or(""))?;
+
+    match header.alg.key_wrap_length() {
+        None => {
+            let length = header.enc.key_length();
+
+            Ok((
+                concat_kdf(z, &header.enc.to_string(), &apu, &apv, length),
+                vec![],
+            ))
+        }
+        Some(length) => {
+            let kek = concat_kdf(z, &header.alg.to_string(), &apu, &apv, length);
+
+            match wrapped {
+                Some(wrapped) => Ok((unwrap_key(&kek, wrapped)?, wrapped.to_vec())),
+                None => {
+                    let cek = random_bytes(header.enc.key_length());
+                    let wrapped = wrap_key(&kek, &cek)?;
+
+                    Ok((cek, wrapped))
+                }
+            }
+        }
+    }
+}
+
+// Recovers the content encryption key of a token from its encrypted key
+fn recover_content_key(
+    header: &JweHeader,
+    encrypted_key: &[u8],
+    secret: &[u8],
+    format: KeyFormat,
+) -> Result<Vec<u8>> {
+    let key_type = KeyType::from_secret(secret, format);
+
+    check_key_type(header.alg, key_type)?;
+
+    let cek = match header.alg {
+        KeyManagementAlgorithm::Dir => symmetric_key(secret, format)?,
+        KeyManagementAlgorithm::A128KW
+        | KeyManagementAlgorithm::A192KW
+        | KeyManagementAlgorithm::A256KW => {
+            unwrap_key(&symmetric_key(secret, format)?, encrypted_key)?
+        }
+        KeyManagementAlgorithm::RsaOaep | KeyManagementAlgorithm::RsaOaep256 => {
+            let private_key = rsa_keys(secret, format)?.1.ok_or_else(|| {
+                Error::Crypto("a private key is needed to decrypt the token".to_string())
+            })?;
+            let padding = match header.alg {
+                KeyManagementAlgorithm::RsaOaep => Oaep::new::<sha1::Sha1>(),
+                _ => Oaep::new::<Sha256>(),
+            };
+
+            private_key
+                .decrypt(padding, encrypted_key)
+                .map_err(|_| decryption_error())?
+        }
+        _ => {
+            let epk = header
+                .epk
+                .as_ref()
+                .ok_or_else(|| Error::Malformed("the token has no epk".to_string()))?;
+            let z = match key_type {
+                Some(KeyType::Ec("P-256")) => {
+                    ecdh_recipient::<NistP256>(secret, format, "P-256", epk)?
+                }
+                Some(KeyType::Ec("P-384")) => {
+                    ecdh_recipient::<NistP384>(secret, format, "P-384", epk)?
+                }
+                _ => ecdh_recipient::<NistP521>(secret, format, "P-521", epk)?,
+            };
+
+            ecdh_content_key(header, &z, Some(encrypted_key))?.0
+        }
+    };
+
+    if cek.len() == header.enc.key_length() {
+        Ok(cek)
+    } else {
+        Err(decryption_error())
+    }
+}
+
+// The authentication tag of AES-CBC with HMAC-SHA-256 (RFC 7518 5.2.2)
+fn cbc_hmac(mac_key: &[u8], aad: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Hmac<Sha256>> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key).map_err(|_| wrong_length())?;
+
+    mac.update(aad);
+    mac.update(iv);
+    mac.update(ciphertext);
+    mac.update(&((aad.len() as u64) * 8).to_be_bytes());
+
+    Ok(mac)
+}
+
+fn gcm_nonce(iv: &[u8]) -> Option<Nonce<U12>> {
+    <[u8; 12]>::try_from(iv).ok().map(Nonce::from)
+}
+
+// Encrypts the content, returning the ciphertext and its authentication tag
+fn encrypt_content(
+    enc: ContentEncryptionAlgorithm,
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let payload = AeadPayload {
+        msg: plaintext,
+        aad,
+    };
+    let sealed = match enc {
+        ContentEncryptionAlgorithm::A128GCM => Aes128Gcm::new_from_slice(cek)
+            .ok()
+            .and_then(|cipher| cipher.encrypt(&gcm_nonce(iv)?, payload).ok()),
+        ContentEncryptionAlgorithm::A256GCM => Aes256Gcm::new_from_slice(cek)
+            .ok()
+            .and_then(|cipher| cipher.encrypt(&gcm_nonce(iv)?, payload).ok()),
+        ContentEncryptionAlgorithm::A128CBC_HS256 => {
+            let (mac_key, enc_key) = cek.split_at(16);
+            let ciphertext = cbc::Encryptor::<aes::Aes128>::new_from_slices(enc_key, iv)
+                .map_err(|_| wrong_length())?
+                .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+            let tag = cbc_hmac(mac_key, aad, iv, &ciphertext)?
+                .finalize()
+                .into_bytes();
+
+            return Ok((ciphertext, tag[..16].to_vec()));
+        }
+    }
+    .ok_or_else(|| Error::Crypto("the content couldn't be encrypted".to_string()))?;
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - 16);
+
+    Ok((ciphertext.to_vec(), tag.to_vec()))
+}
+
+fn decrypt_content(
+    enc: ContentEncryptionAlgorithm,
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Result<Vec<u8>> {
+    if iv.len() != enc.iv_length() {
+        return Err(Error::Malformed(
+            "the initialization vector isn't the right length".to_string(),
+        ));
+    }
+    // Every content encryption algorithm has a 16 byte tag, and a shorter one would be
+    // easier to forge
+    if tag.len() != 16 {
+        return Err(decryption_error());
+    }
+
+    let sealed = [ciphertext, tag].concat();
+    let payload = AeadPayload { msg: &sealed, aad };
+    let plaintext = match enc {
+        ContentEncryptionAlgorithm::A128GCM => Aes128Gcm::new_from_slice(cek)
+            .ok()
+            .and_then(|cipher| cipher.decrypt(&gcm_nonce(iv)?, payload).ok()),
+        ContentEncryptionAlgorithm::A256GCM => Aes256Gcm::new_from_slice(cek)
+            .ok()
+            .and_then(|cipher| cipher.decrypt(&gcm_nonce(iv)?, payload).ok()),
+        ContentEncryptionAlgorithm::A128CBC_HS256 => {
+            let (mac_key, enc_key) = cek.split_at(16);
+
+            // The tag is checked before decrypting anything, so padding errors can't leak.
+            // It's the whole left half of the MAC, since its length was checked above.
+            cbc_hmac(mac_key, aad, iv, ciphertext)?
+                .verify_truncated_left(tag)
+                .map_err(|_| decryption_error())?;
+
+            cbc::Decryptor::<aes::Aes128>::new_from_slices(enc_key, iv)
+                .ok()
+                .and_then(|cipher| cipher.decrypt_padded_vec_mut::<Pkcs7>(ciphertext).ok())
+        }
+    };
+
+    plaintext.ok_or_else(decryption_error)
+}
+
+/// Encrypts `plaintext` for the holder of the key, returning the JWE in its compact
+/// serialization. The key can be prefixed with @ to read it from a file.
+pub fn encrypt(
+    plaintext: &[u8],
+    header: &JweHeader,
+    secret_string: &str,
+    format: Option<KeyFormat>,
+) -> Result<String> {
+    let (secret, format) = read_secret(secret_string, format)?;
+    let mut header = header.clone();
+
+    // The kid of a JWK lets the recipient pick it out of their JWK Set
+    if let (None, KeyFormat::JWK) = (&header.kid, format) {
+        header.kid = serde_json::from_slice::<Value>(&secret)?["kid"]
+            .as_str()
+            .map(str::to_string);
+    }
+
+    let (cek, encrypted_key) = content_key(&mut header, &secret, format)?;
+    let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+    let iv = random_bytes(header.enc.iv_length());
+    let (ciphertext, tag) =
+        encrypt_content(header.enc, &cek, &iv, protected.as_bytes(), plaintext)?;
+
+    Ok([
+        protected,
+        URL_SAFE_NO_PAD.encode(encrypted_key),
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag),
+    ]
+    .join("."))
+}
+
+/// Reads the protected header of a JWE without decrypting it
+pub fn insecure_decode_jwe_header(token: &str) -> Result<JweHeader> {
+    let protected = token
+        .trim()
+        .split('.')
+        .next()
+        .ok_or_else(Error::not_a_jwt)?;
+
+    Ok(serde_json::from_slice(&b64_decode(protected)?)?)
+}
+
+// Picks the key a JWE was encrypted for out of a JWK Set, by its kid
+fn recipient_jwk(secret: Vec<u8>, kid: Option<&str>) -> Result<Vec<u8>> {
+    let jwk: Value = serde_json::from_slice(&secret)?;
+
+    match (&jwk["keys"], kid) {
+        (Value::Array(keys), Some(kid)) => keys
+            .iter()
+            .find(|key| key["kid"] == kid)
+            .ok_or_else(decryption_error)
+            .and_then(|key| Ok(serde_json::to_vec(key)?)),
+        (Value::Array(_), None) => Err(decryption_error()),
+        _ => Ok(secret),
+    }
+}
+
+/// Decrypts a JWE in its compact serialization, returning its header and content. The
+/// key can be prefixed with @ to read it from a file, and can be a JWK Set when the
+/// token has a `kid`.
+pub fn decrypt(
+    token: &str,
+    secret_string: &str,
+    format: Option<KeyFormat>,
+) -> Result<(JweHeader, Vec<u8>)> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+
+    if parts.len() != 5 {
+        return Err(Error::not_a_jwt());
+    }
+
+    let header: JweHeader = serde_json::from_slice(&b64_decode(parts[0])?)?;
+
+    if header.zip.is_some() {
+        return Err(Error::Malformed(
+            "compressed tokens aren't supported".to_string(),
+        ));
+    }
+
+    let (secret, format) = read_secret(secret_string, format)?;
+    let secret = match format {
+        KeyFormat::JWK => recipient_jwk(secret, header.kid.as_deref())?,
+        _ => secret,
+    };
+    let cek = recover_content_key(&header, &b64_decode(parts[1])?, &secret, format)?;
+    let plaintext = decrypt_content(
+        header.enc,
+        &cek,
+        &b64_decode(parts[2])?,
+        parts[0].as_bytes(),
+        &b64_decode(parts[3])?,
+        &b64_decode(parts[4])?,
+    )?;
+
+    Ok((header, plaintext))
+}
@@ -0,0 +1,312 @@
+use crate::error::{Error, Result};
+use crate::keys::{
+    ec_public_key, ec_secret_key, pem_contents, read_secret, KeyFormat, KeyType,
+    ED25519_PKCS8_PREFIX, ED25519_SPKI_PREFIX,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use elliptic_curve::pkcs8::AssociatedOid;
+use elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use elliptic_curve::{AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey};
+use k256::Secp256k1;
+use p256::NistP256;
+use p384::NistP384;
+use p521::NistP521;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::{PrivateKeyParts, PublicKeyParts};
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::str;
+
+// The members of a JWK which are private, and removed from public keys
+const PRIVATE_PARAMETERS: [&str; 8] = ["d", "p", "q", "dp", "dq", "qi", "oth", "k"];
+
+fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn jwk_object(parameters: Vec<(&str, String)>) -> Map<String, Value> {
+    parameters
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), Value::String(value)))
+        .collect()
+}
+
+fn invalid_key(format: KeyFormat, reason: &str) -> Error {
+    Error::KeyParse {
+        format,
+        reason: reason.to_string(),
+    }
+}
+
+fn invalid_rsa_key() -> Error {
+    Error::Crypto("the RSA key is invalid".to_string())
+}
+
+// The parameters of an RSA JWK, including the private ones when the private key is given
+pub(crate) fn rsa_parameters(
+    public_key: &RsaPublicKey,
+    private_key: Option<&RsaPrivateKey>,
+) -> Result<Map<String, Value>> {
+    let int = |value: &BigUint| b64(&value.to_bytes_be());
+    let mut parameters = vec![
+        ("kty", "RSA".to_string()),
+        ("n", int(public_key.n())),
+        ("e", int(public_key.e())),
+    ];
+
+    if let Some(key) = private_key {
+        let mut key = key.clone();
+
+        key.precompute().map_err(|_| invalid_rsa_key())?;
+
+        let crt =
+            |value: Option<BigUint>| value.map(|value| int(&value)).ok_or_else(invalid_rsa_key);
+        let primes = key.primes();
+
+        parameters.extend(vec![
+            ("d", int(key.d())),
+            ("p", int(&primes[0])),
+            ("q", int(&primes[1])),
+            ("dp", crt(key.dp().cloned())?),
+            ("dq", crt(key.dq().cloned())?),
+            ("qi", crt(key.crt_coefficient())?),
+        ]);
+    }
+
+    Ok(jwk_object(parameters))
+}
+
+// The parameters of an EC JWK, including `d` when the private key is given
+pub(crate) fn ec_parameters<C>(
+    public_key: &PublicKey<C>,
+    private_key: Option<&SecretKey<C>>,
+    curve: &str,
+) -> Result<Map<String, Value>>
+where
+    C: CurveArithmetic,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let point = public_key.to_encoded_point(false);
+    let mut parameters = match (point.x(), point.y()) {
+        (Some(x), Some(y)) => vec![
+            ("kty", "EC".to_string()),
+            ("crv", curve.to_string()),
+            ("x", b64(x)),
+            ("y", b64(y)),
+        ],
+        _ => return Err(Error::Crypto("the EC key is invalid".to_string())),
+    };
+
+    if let Some(key) = private_key {
+        parameters.push(("d", b64(&key.to_bytes())));
+    }
+
+    Ok(jwk_object(parameters))
+}
+
+// The parameters of an Ed25519 JWK, including `d` when the private key's seed is given
+pub(crate) fn ed25519_parameters(public_key: &[u8], seed: Option<&[u8]>) -> Map<String, Value> {
+    let mut parameters = vec![
+        ("kty", "OKP".to_string()),
+        ("crv", "Ed25519".to_string()),
+        ("x", b64(public_key)),
+    ];
+
+    if let Some(seed) = seed {
+        parameters.push(("d", b64(seed)));
+    }
+
+    jwk_object(parameters)
+}
+
+// The parameters of a symmetric JWK
+pub(crate) fn oct_parameters(secret: &[u8]) -> Map<String, Value> {
+    jwk_object(vec![("kty", "oct".to_string()), ("k", b64(secret))])
+}
+
+// Whether a JWK has any private parameters, which symmetric keys always have
+pub(crate) fn is_private(jwk: &Value) -> bool {
+    PRIVATE_PARAMETERS
+        .iter()
+        .any(|name| jwk.get(name).is_some())
+}
+
+/// Removes the private parameters from a JWK. Symmetric keys have nothing
+/// public, so they give None.
+pub fn public_jwk(jwk: &Value) -> Option<Value> {
+    match jwk {
+        Value::Object(_) if jwk["kty"] == "oct" => None,
+        Value::Object(members) => Some(Value::Object(
+            members
+                .iter()
+                .filter(|(name, _)| !PRIVATE_PARAMETERS.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        )),
+        _ => None,
+    }
+}
+
+// The members of each key type which make up its thumbprint, in lexicographic order
+fn thumbprint_members(kty: &str) -> Option<&'static [&'static str]> {
+    match kty {
+        "RSA" => Some(&["e", "kty", "n"]),
+        "EC" => Some(&["crv", "kty", "x", "y"]),
+        "OKP" => Some(&["crv", "kty", "x"]),
+        "oct" => Some(&["k", "kty"]),
+        _ => None,
+    }
+}
+
+/// Computes the RFC 7638 thumbprint of a JWK, which is what generated keys use as their `kid`
+pub fn thumbprint(jwk: &Value) -> Result<String> {
+    let members = jwk["kty"]
+        .as_str()
+        .and_then(thumbprint_members)
+        .ok_or_else(|| invalid_key(KeyFormat::JWK, "its kty isn't known"))?;
+    let mut required = BTreeMap::new();
+
+    for member in members {
+        match &jwk[*member] {
+            Value::String(value) => required.insert(*member, value),
+            _ => {
+                return Err(invalid_key(
+                    KeyFormat::JWK,
+                    &format!("it doesn't have the {} its kty needs", member),
+                ))
+            }
+        };
+    }
+
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(serde_json::to_vec(&required)?)))
+}
+
+// Reads a PKCS#1, PKCS#8 or SPKI RSA key, either private or public
+fn rsa_key_parameters(secret: &[u8], format: KeyFormat) -> Result<Map<String, Value>> {
+    let private_key = match format {
+        KeyFormat::PEM => str::from_utf8(secret).ok().and_then(|pem| {
+            RsaPrivateKey::from_pkcs1_pem(pem)
+                .ok()
+                .or_else(|| RsaPrivateKey::from_pkcs8_pem(pem).ok())
+        }),
+        _ => RsaPrivateKey::from_pkcs1_der(secret)
+            .ok()
+            .or_else(|| RsaPrivateKey::from_pkcs8_der(secret).ok()),
+    };
+
+    if let Some(key) = private_key {
+        return rsa_parameters(&key.to_public_key(), Some(&key));
+    }
+
+    let public_key = match format {
+        KeyFormat::PEM => str::from_utf8(secret).ok().and_then(|pem| {
+            RsaPublicKey::from_pkcs1_pem(pem)
+                .ok()
+                .or_else(|| RsaPublicKey::from_public_key_pem(pem).ok())
+        }),
+        _ => RsaPublicKey::from_pkcs1_der(secret)
+            .ok()
+            .or_else(|| RsaPublicKey::from_public_key_der(secret).ok()),
+    };
+
+    match public_key {
+        Some(key) => rsa_parameters(&key, None),
+        None => Err(invalid_key(format, "it isn't a valid RSA key")),
+    }
+}
+
+// Reads a raw EC public point, which doesn't say what curve it's on. Points are only valid
+// on one of the curves, so each is tried in turn.
+fn ec_point_parameters(point: &[u8], format: KeyFormat) -> Result<Map<String, Value>> {
+    if let Ok(key) = PublicKey::<NistP256>::from_sec1_bytes(point) {
+        ec_parameters(&key, None, "P-256")
+    } else if let Ok(key) = PublicKey::<NistP384>::from_sec1_bytes(point) {
+        ec_parameters(&key, None, "P-384")
+    } else if let Ok(key) = PublicKey::<NistP521>::from_sec1_bytes(point) {
+        ec_parameters(&key, None, "P-521")
+    } else if let Ok(key) = PublicKey::<Secp256k1>::from_sec1_bytes(point) {
+        ec_parameters(&key, None, "secp256k1")
+    } else {
+        Err(invalid_key(format, "it isn't an RSA key or an EC point"))
+    }
+}
+
+fn ec_key_parameters<C>(secret: &[u8], format: KeyFormat, curve: &str) -> Result<Map<String, Value>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    match ec_secret_key::<C>(secret, format, curve) {
+        Ok(key) => ec_parameters(&key.public_key(), Some(&key), curve),
+        Err(_) => {
+            let key =
+                ec_public_key::<C>(secret, format, curve).map_err(|err| Error::key(format, err))?;
+
+            ec_parameters(&key, None, curve)
+        }
+    }
+}
+
+fn ed25519_key_parameters(secret: &[u8], format: KeyFormat) -> Result<Map<String, Value>> {
+    let der = match format {
+        KeyFormat::PEM => pem_contents(secret).map(|(_, der)| der),
+        _ => Some(secret.to_vec()),
+    }
+    .ok_or_else(|| invalid_key(format, "it isn't a valid Ed25519 key"))?;
+
+    if let Some(seed) = der.strip_prefix(&ED25519_PKCS8_PREFIX[..]) {
+        let seed: [u8; 32] = seed
+            .try_into()
+            .map_err(|_| invalid_key(format, "it isn't a valid Ed25519 key"))?;
+        let public_key = ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key();
+
+        Ok(ed25519_parameters(public_key.as_bytes(), Some(&seed)))
+    } else if let Some(public_key) = der.strip_prefix(&ED25519_SPKI_PREFIX[..]) {
+        Ok(ed25519_parameters(public_key, None))
+    } else {
+        Err(invalid_key(format, "it isn't a valid Ed25519 key"))
+    }
+}
+
+/// Converts a PEM, DER or JWK public or private key into a JWK, keeping its
+/// private parameters. The key can be prefixed with @ to read it from a file.
+pub fn jwk_from_key(secret_string: &str, format: Option<KeyFormat>) -> Result<Value> {
+    let (secret, format) = read_secret(secret_string, format)?;
+
+    key_jwk(&secret, format)
+}
+
+// Converts a key which has already been read into a JWK
+pub(crate) fn key_jwk(secret: &[u8], format: KeyFormat) -> Result<Value> {
+    let parameters = match (KeyType::from_secret(secret, format), format) {
+        (_, KeyFormat::JWK) => {
+            match serde_json::from_slice(secret).map_err(|err| Error::key(format, err.into()))? {
+                Value::Object(jwk) if jwk.get("kty").is_some_and(Value::is_string) => jwk,
+                _ => return Err(invalid_key(format, "it doesn't have a kty")),
+            }
+        }
+        (Some(KeyType::Rsa), _) => rsa_key_parameters(secret, format)?,
+        (None, _) => {
+            rsa_key_parameters(secret, format).or_else(|_| ec_point_parameters(secret, format))?
+        }
+        (Some(KeyType::Ec("P-256")), _) => ec_key_parameters::<NistP256>(secret, format, "P-256")?,
+        (Some(KeyType::Ec("P-384")), _) => ec_key_parameters::<NistP384>(secret, format, "P-384")?,
+        (Some(KeyType::Ec("P-521")), _) => ec_key_parameters::<NistP521>(secret, format, "P-521")?,
+        (Some(KeyType::Ec("secp256k1")), _) => {
+            ec_key_parameters::<Secp256k1>(secret, format, "secp256k1")?
+        }
+        (Some(KeyType::Ed25519), _) => ed25519_key_parameters(secret, format)?,
+        (Some(KeyType::Ec(_)), _) | (Some(KeyType::Symmetric), _) => {
+            return Err(invalid_key(format, "it isn't an RSA, EC or Ed25519 key"))
+        }
+    };
+
+    Ok(Value::Object(parameters))
+}
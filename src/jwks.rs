@@ -0,0 +1,100 @@
+use crate::error::{Error, Result};
+use crate::jwk::{is_private, public_jwk, thumbprint};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// A JWK Set, as found in `{"keys": [...]}` files
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Value>,
+}
+
+impl JwkSet {
+    /// Reads a set from a file. A file which doesn't exist is an empty set.
+    pub fn read(path: &Path) -> Result<JwkSet> {
+        match fs::read(path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(JwkSet::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the set to a file, which only its owner can read when it has private keys
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut contents = serde_json::to_vec_pretty(self)?;
+        let mut options = fs::OpenOptions::new();
+        let private = self.keys.iter().any(is_private);
+
+        contents.push(b'\n');
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        if private {
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(path)?;
+
+        // An existing file keeps its permissions otherwise
+        #[cfg(unix)]
+        if private {
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+
+        Ok(file.write_all(&contents)?)
+    }
+
+    pub fn find(&self, kid: &str) -> Option<&Value> {
+        self.keys.iter().find(|key| key["kid"] == kid)
+    }
+
+    /// Adds a key to the set and returns its kid. Keys without one get their
+    /// RFC 7638 thumbprint. Adding a key which is already in the set replaces
+    /// it, so a private key can take the place of its public key.
+    pub fn add(&mut self, mut jwk: Value) -> Result<String> {
+        let kid = match jwk["kid"].as_str() {
+            Some(kid) => kid.to_string(),
+            None => thumbprint(&jwk)?,
+        };
+
+        if let Value::Object(members) = &mut jwk {
+            members.insert("kid".to_string(), Value::String(kid.clone()));
+        }
+
+        match self.keys.iter().position(|key| key["kid"] == kid.as_str()) {
+            Some(index) if thumbprint(&self.keys[index])? == thumbprint(&jwk)? => {
+                self.keys[index] = jwk
+            }
+            Some(_) => {
+                return Err(Error::Malformed(format!(
+                    "the set already has a different key with the kid {}",
+                    kid
+                )))
+            }
+            None => self.keys.push(jwk),
+        }
+
+        Ok(kid)
+    }
+
+    /// Removes the key with `kid`, returning whether it was in the set
+    pub fn remove(&mut self, kid: &str) -> bool {
+        let count = self.keys.len();
+
+        self.keys.retain(|key| key["kid"] != kid);
+
+        self.keys.len() != count
+    }
+
+    /// The set to publish, without private parameters or symmetric keys
+    pub fn public(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(public_jwk).collect(),
+        }
+    }
+}
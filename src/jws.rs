@@ -0,0 +1,373 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result, ValidationError};
+use crate::keys::{DecodingKey, EncodingKey};
+use crate::payload::Payload;
+use crate::validation::{validate, Validation};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// A JOSE header. Only `alg` is required, everything else is skipped when not set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    pub alg: SupportedAlgorithms,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jku: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwk: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5u: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5c: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5t: Option<String>,
+    #[serde(rename = "x5t#S256", skip_serializing_if = "Option::is_none")]
+    pub x5t_s256: Option<String>,
+    /// Whether the payload is base64url encoded. False for unencoded payloads (RFC 7797).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b64: Option<bool>,
+    /// The header parameters which have to be understood to verify the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crit: Option<Vec<String>>,
+    /// Any other header parameters, such as private ones
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl Header {
+    /// A header for a JWT signed with `alg`
+    pub fn new(alg: SupportedAlgorithms) -> Self {
+        Header {
+            typ: Some("JWT".to_string()),
+            alg,
+            cty: None,
+            jku: None,
+            jwk: None,
+            kid: None,
+            x5u: None,
+            x5c: None,
+            x5t: None,
+            x5t_s256: None,
+            b64: None,
+            crit: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the payload of the token is itself a JWT
+    pub fn is_nested(&self) -> bool {
+        self.cty
+            .as_deref()
+            .is_some_and(|cty| cty.eq_ignore_ascii_case("JWT"))
+    }
+}
+
+/// The ways a signed token can be serialized (RFC 7515 section 7)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Serialization {
+    Compact,
+    /// The JSON serialization with a single signature at the top level
+    JsonFlattened,
+    /// The JSON serialization with a `signatures` array
+    JsonGeneral,
+}
+
+impl Serialization {
+    pub fn variants() -> [&'static str; 3] {
+        ["compact", "json-flattened", "json-general"]
+    }
+
+    /// Parses a `--serialization` value. Anything unrecognised is treated as compact.
+    pub fn from_string(serialization: &str) -> Serialization {
+        match serialization {
+            "json-flattened" => Serialization::JsonFlattened,
+            "json-general" => Serialization::JsonGeneral,
+            _ => Serialization::Compact,
+        }
+    }
+}
+
+/// One signature of a token in the JSON serialization
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonSignature {
+    /// The protected header, payload and signature joined into a compact token
+    pub compact: String,
+    /// The header parameters which aren't protected by the signature
+    pub unprotected: Option<Value>,
+}
+
+/// A decoded token, with the header of a signed token unless said otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenData<T, H = Header> {
+    pub header: H,
+    pub claims: T,
+}
+
+fn b64_encode_part<T: serde::Serialize>(part: &T) -> Result<String> {
+    Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(part)?))
+}
+
+fn b64_decode_part<T: DeserializeOwned>(part: &str) -> Result<T> {
+    Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part)?)?)
+}
+
+// Splits a compact token into its signing input, header, payload and signature. Only
+// unencoded payloads can have dots, so the header and signature are the outer parts.
+fn split_token(token: &str) -> Result<(&str, &str, &str, &str)> {
+    let (message, signature) = token.rsplit_once('.').ok_or_else(Error::not_a_jwt)?;
+    let (header, payload) = message.split_once('.').ok_or_else(Error::not_a_jwt)?;
+
+    Ok((message, header, payload, signature))
+}
+
+// The header parameters which are understood when a token lists them as critical
+const UNDERSTOOD_CRITICAL_PARAMETERS: [&str; 1] = ["b64"];
+
+// Refuses tokens with critical header parameters which aren't understood (RFC 7515 4.1.11)
+fn check_critical_parameters(header: &Header) -> Result<()> {
+    let critical = header.crit.as_deref().unwrap_or_default();
+
+    if let Some(unknown) = critical
+        .iter()
+        .find(|name| !UNDERSTOOD_CRITICAL_PARAMETERS.contains(&name.as_str()))
+    {
+        return Err(Error::Malformed(format!(
+            "the critical header parameter {} isn't supported",
+            unknown
+        )));
+    }
+
+    match (header.b64, critical.iter().any(|name| name == "b64")) {
+        (Some(_), false) => Err(Error::Malformed(
+            "the b64 header parameter has to be listed in crit".to_string(),
+        )),
+        (None, true) => Err(Error::Malformed(
+            "the critical header parameter b64 is missing".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// The payload of a token as bytes, decoding it unless the header says it's unencoded
+fn payload_bytes(header: &Header, payload: &str) -> Result<Vec<u8>> {
+    match header.b64 {
+        Some(false) => Ok(payload.as_bytes().to_vec()),
+        _ if payload.contains('.') => Err(Error::not_a_jwt()),
+        _ => Ok(URL_SAFE_NO_PAD.decode(payload)?),
+    }
+}
+
+// The payload as it appears in a compact token: base64url encoded, unless the header says otherwise
+fn encode_payload(header: &Header, payload: &[u8]) -> Result<String> {
+    match header.b64 {
+        Some(false) => String::from_utf8(payload.to_vec())
+            .map_err(|_| Error::Malformed("unencoded payloads have to be UTF-8".to_string())),
+        _ => Ok(URL_SAFE_NO_PAD.encode(payload)),
+    }
+}
+
+/// Signs `payload` and returns the token in its compact serialization. The payload is
+/// base64url encoded, unless `b64` is false in the header.
+pub fn sign_payload(header: &Header, payload: &[u8], key: &EncodingKey) -> Result<String> {
+    let payload = encode_payload(header, payload)?;
+    let message = [b64_encode_part(header)?, payload].join(".");
+    let signature = key.sign(message.as_bytes(), header.alg)?;
+
+    Ok([message, signature].join("."))
+}
+
+/// Signs `payload` and returns the token without it, as `header..signature`. When `b64` is
+/// false in the header, the payload can be any bytes, as it never has to fit in the token.
+pub fn sign_detached(header: &Header, payload: &[u8], key: &EncodingKey) -> Result<String> {
+    let header_part = b64_encode_part(header)?;
+    let mut message = format!("{}.", header_part).into_bytes();
+
+    match header.b64 {
+        Some(false) => message.extend_from_slice(payload),
+        _ => message.extend_from_slice(URL_SAFE_NO_PAD.encode(payload).as_bytes()),
+    }
+
+    let signature = key.sign(&message, header.alg)?;
+
+    Ok([header_part.as_str(), "", &signature].join("."))
+}
+
+/// Takes the payload out of a token, leaving `header..signature` (RFC 7515 appendix F)
+pub fn detach_payload(token: &str) -> Result<String> {
+    let (_, header, _, signature) = split_token(token)?;
+
+    Ok([header, "", signature].join("."))
+}
+
+/// Whether the payload of a token is detached from it
+pub fn is_detached(token: &str) -> bool {
+    matches!(split_token(token), Ok((_, _, "", _)))
+}
+
+/// Puts a detached payload back into a token, encoding it unless the header says it's
+/// unencoded
+pub fn attach_payload(token: &str, payload: &[u8]) -> Result<String> {
+    let (_, header_part, detached, signature) = split_token(token)?;
+    let header: Header = b64_decode_part(header_part)?;
+
+    if !detached.is_empty() {
+        return Err(Error::Malformed(
+            "the token already has a payload".to_string(),
+        ));
+    }
+
+    let payload = encode_payload(&header, payload)?;
+
+    Ok([header_part, &payload, signature].join("."))
+}
+
+/// Signs `claims` and returns the token in its compact serialization
+pub fn encode<T: serde::Serialize>(
+    header: &Header,
+    claims: &T,
+    key: &EncodingKey,
+) -> Result<String> {
+    sign_payload(header, &serde_json::to_vec(claims)?, key)
+}
+
+/// Signs `claims` once for each signer and returns the token in the JSON serialization.
+/// Only the general syntax can have more than one signature.
+pub fn encode_json<T: serde::Serialize>(
+    signers: &[(&Header, &EncodingKey)],
+    claims: &T,
+    serialization: Serialization,
+) -> Result<String> {
+    let payload = b64_encode_part(claims)?;
+    let mut signatures = vec![];
+
+    for (header, key) in signers {
+        let protected = b64_encode_part(header)?;
+        let signature = key.sign(format!("{}.{}", protected, payload).as_bytes(), header.alg)?;
+
+        signatures.push(json!({"protected": protected, "signature": signature}));
+    }
+
+    let token = match (serialization, signatures.len()) {
+        (Serialization::JsonGeneral, _) => json!({"payload": payload, "signatures": signatures}),
+        (Serialization::JsonFlattened, 1) => {
+            let mut token = signatures.remove(0);
+
+            token["payload"] = Value::String(payload);
+            token
+        }
+        _ => {
+            return Err(Error::Malformed(
+                "only the general JSON serialization can have more than one signature".to_string(),
+            ))
+        }
+    };
+
+    Ok(serde_json::to_string(&token)?)
+}
+
+/// Whether a token is in the JSON serialization rather than the compact one
+pub fn is_json_serialization(token: &str) -> bool {
+    token.trim_start().starts_with('{')
+}
+
+// A member of a JSON serialized token which has to be a string
+fn json_member<'a>(object: &'a Value, name: &str) -> Result<&'a str> {
+    object[name].as_str().ok_or_else(Error::not_a_jwt)
+}
+
+/// Splits a token in either syntax of the JSON serialization into its signatures. Each
+/// one is turned into a compact token, so it can be verified like any other.
+pub fn split_json_serialization(token: &str) -> Result<Vec<JsonSignature>> {
+    let token: Value = serde_json::from_str(token)?;
+    let payload = json_member(&token, "payload")?;
+    let signatures = match &token["signatures"] {
+        Value::Array(signatures) => signatures.iter().collect(),
+        Value::Null => vec![&token],
+        _ => return Err(Error::not_a_jwt()),
+    };
+
+    signatures
+        .into_iter()
+        .map(|signature| {
+            Ok(JsonSignature {
+                compact: [
+                    json_member(signature, "protected")?,
+                    payload,
+                    json_member(signature, "signature")?,
+                ]
+                .join("."),
+                unprotected: signature.get("header").cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Decodes a token without checking its signature or any of its claims. Tokens with
+/// critical header parameters which aren't understood are still refused.
+pub fn insecure_decode(token: &str) -> Result<TokenData<Payload>> {
+    let (header, payload) = insecure_decode_payload(token)?;
+
+    check_critical_parameters(&header)?;
+
+    Ok(TokenData {
+        header,
+        claims: serde_json::from_slice(&payload)?,
+    })
+}
+
+/// Reads the header of a token, and its payload as it is, without checking its signature.
+/// The payload of a nested token is the token inside it.
+pub fn insecure_decode_payload(token: &str) -> Result<(Header, Vec<u8>)> {
+    let (_, header, payload, _) = split_token(token)?;
+    let header: Header = b64_decode_part(header)?;
+    let payload = payload_bytes(&header, payload)?;
+
+    Ok((header, payload))
+}
+
+/// Verifies the signature of a token with `key`, as long as it's signed with one of
+/// `algorithms`, without reading its payload
+pub fn verify_signature(
+    token: &str,
+    key: &DecodingKey,
+    algorithms: &[SupportedAlgorithms],
+) -> Result<Header> {
+    let (message, header, _, signature) = split_token(token)?;
+    let header: Header = b64_decode_part(header)?;
+
+    check_critical_parameters(&header)?;
+
+    if !algorithms.contains(&header.alg) {
+        return Err(ValidationError::Algorithm.into());
+    }
+
+    if !key.verify(signature, message.as_bytes(), header.alg)? {
+        return Err(Error::InvalidSignature);
+    }
+
+    Ok(header)
+}
+
+/// Verifies the signature of a token with `key`, then validates its claims
+pub fn decode(
+    token: &str,
+    key: &DecodingKey,
+    validation: &Validation,
+) -> Result<TokenData<Payload>> {
+    let header = verify_signature(token, key, &validation.algorithms)?;
+    let (_, _, payload, _) = split_token(token)?;
+    let claims: Payload = serde_json::from_slice(&payload_bytes(&header, payload)?)?;
+
+    validate(&claims, validation)?;
+
+    Ok(TokenData { header, claims })
+}
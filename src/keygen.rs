@@ -0,0 +1,260 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result};
+use crate::jwk::{
+    ec_parameters, ed25519_parameters, oct_parameters, public_jwk, rsa_parameters, thumbprint,
+};
+use crate::keys::{KeyFormat, ED25519_PKCS8_PREFIX, ED25519_SPKI_PREFIX};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use elliptic_curve::pkcs8::der::pem;
+use elliptic_curve::pkcs8::{AssociatedOid, EncodePrivateKey, EncodePublicKey, LineEnding};
+use elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use elliptic_curve::{AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey};
+use k256::Secp256k1;
+use p256::NistP256;
+use p384::NistP384;
+use p521::NistP521;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::pkcs1::{EncodeRsaPrivateKey, EncodeRsaPublicKey};
+use rsa::RsaPrivateKey;
+use serde_json::{Map, Value};
+
+/// What kind of key to generate. Construct it with [`KeygenOptions::new`].
+#[derive(Debug)]
+pub struct KeygenOptions {
+    algorithm: SupportedAlgorithms,
+    key_format: KeyFormat,
+    kid: Option<String>,
+    rsa_bits: usize,
+}
+
+impl KeygenOptions {
+    /// Starts a new set of options generating a PEM key for `algorithm`. RSA
+    /// keys are 2048 bits long.
+    pub fn new(algorithm: SupportedAlgorithms) -> Self {
+        KeygenOptions {
+            algorithm,
+            key_format: KeyFormat::PEM,
+            kid: None,
+            rsa_bits: 2048,
+        }
+    }
+
+    /// The format of the key. HMAC secrets are plain text unless this is JWK,
+    /// and can't be DER.
+    pub fn key_format(mut self, format: KeyFormat) -> Self {
+        self.key_format = format;
+        self
+    }
+
+    /// The `kid` of the generated JWKs. When not set, the RFC 7638 thumbprint of the key is used.
+    pub fn kid(mut self, kid: &str) -> Self {
+        self.kid = Some(kid.to_string());
+        self
+    }
+
+    pub fn rsa_bits(mut self, bits: usize) -> Self {
+        self.rsa_bits = bits;
+        self
+    }
+}
+
+/// A generated key, encoded so that `encode` and `decode` can load it with the same format
+#[derive(Debug)]
+pub struct GeneratedKey {
+    /// The private key, or the secret for HMAC algorithms
+    pub private_key: Vec<u8>,
+    /// The public key. HMAC secrets don't have one.
+    pub public_key: Option<Vec<u8>>,
+}
+
+fn encoding_error<E>(_: E) -> Error {
+    Error::Crypto("the key couldn't be encoded".to_string())
+}
+
+// Adds the kid, alg and use to the private JWK of a key, and derives its public JWK
+fn jwk_pair(options: &KeygenOptions, mut jwk: Map<String, Value>) -> Result<(Value, Value)> {
+    let kid = match &options.kid {
+        Some(kid) => kid.clone(),
+        None => thumbprint(&Value::Object(jwk.clone()))?,
+    };
+
+    jwk.insert("kid".to_string(), Value::String(kid));
+    jwk.insert(
+        "alg".to_string(),
+        Value::String(options.algorithm.to_string()),
+    );
+
+    if jwk["kty"] != "oct" {
+        jwk.insert("use".to_string(), Value::String("sig".to_string()));
+    }
+
+    let jwk = Value::Object(jwk);
+    let public_jwk = public_jwk(&jwk).unwrap_or(Value::Null);
+
+    Ok((jwk, public_jwk))
+}
+
+fn jwk_key(options: &KeygenOptions, jwk: Map<String, Value>) -> Result<GeneratedKey> {
+    let (private_jwk, public_jwk) = jwk_pair(options, jwk)?;
+
+    Ok(GeneratedKey {
+        private_key: serde_json::to_vec_pretty(&private_jwk)?,
+        public_key: Some(serde_json::to_vec_pretty(&public_jwk)?),
+    })
+}
+
+fn hmac_secret(options: &KeygenOptions, length: usize) -> Result<GeneratedKey> {
+    let mut secret = vec![0u8; length];
+
+    OsRng.fill_bytes(&mut secret);
+
+    let private_key = match options.key_format {
+        // Printed as base64url so it can be passed to `--secret` as it is
+        KeyFormat::PEM => URL_SAFE_NO_PAD.encode(&secret).into_bytes(),
+        KeyFormat::DER => {
+            return Err(Error::Crypto(
+                "HMAC secrets can't be DER encoded".to_string(),
+            ))
+        }
+        KeyFormat::JWK => {
+            let (jwk, _) = jwk_pair(options, oct_parameters(&secret))?;
+
+            serde_json::to_vec_pretty(&jwk)?
+        }
+    };
+
+    Ok(GeneratedKey {
+        private_key,
+        public_key: None,
+    })
+}
+
+fn rsa_key_pair(options: &KeygenOptions) -> Result<GeneratedKey> {
+    let key = RsaPrivateKey::new(&mut OsRng, options.rsa_bits)
+        .map_err(|_| Error::Crypto("unable to generate the key".to_string()))?;
+    let public_key = key.to_public_key();
+
+    match options.key_format {
+        KeyFormat::PEM => Ok(GeneratedKey {
+            private_key: key
+                .to_pkcs8_pem(LineEnding::LF)
+                .map_err(encoding_error)?
+                .as_bytes()
+                .to_vec(),
+            public_key: Some(
+                public_key
+                    .to_public_key_pem(LineEnding::LF)
+                    .map_err(encoding_error)?
+                    .into_bytes(),
+            ),
+        }),
+        // jsonwebtoken only reads PKCS#1 DER keys
+        KeyFormat::DER => Ok(GeneratedKey {
+            private_key: key
+                .to_pkcs1_der()
+                .map_err(encoding_error)?
+                .as_bytes()
+                .to_vec(),
+            public_key: Some(
+                public_key
+                    .to_pkcs1_der()
+                    .map_err(encoding_error)?
+                    .as_bytes()
+                    .to_vec(),
+            ),
+        }),
+        KeyFormat::JWK => jwk_key(options, rsa_parameters(&public_key, Some(&key))?),
+    }
+}
+
+fn ec_key_pair<C>(options: &KeygenOptions, curve: &str) -> Result<GeneratedKey>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let key = SecretKey::<C>::random(&mut OsRng);
+    let public_key: PublicKey<C> = key.public_key();
+
+    match options.key_format {
+        KeyFormat::PEM => Ok(GeneratedKey {
+            private_key: key
+                .to_pkcs8_pem(LineEnding::LF)
+                .map_err(encoding_error)?
+                .as_bytes()
+                .to_vec(),
+            public_key: Some(
+                public_key
+                    .to_public_key_pem(LineEnding::LF)
+                    .map_err(encoding_error)?
+                    .into_bytes(),
+            ),
+        }),
+        KeyFormat::DER => Ok(GeneratedKey {
+            private_key: key
+                .to_pkcs8_der()
+                .map_err(encoding_error)?
+                .as_bytes()
+                .to_vec(),
+            public_key: Some(
+                public_key
+                    .to_public_key_der()
+                    .map_err(encoding_error)?
+                    .into_vec(),
+            ),
+        }),
+        KeyFormat::JWK => jwk_key(options, ec_parameters(&public_key, Some(&key), curve)?),
+    }
+}
+
+fn ed25519_key_pair(options: &KeygenOptions) -> Result<GeneratedKey> {
+    let mut seed = [0u8; 32];
+
+    OsRng.fill_bytes(&mut seed);
+
+    let public_key = ed25519_dalek::SigningKey::from_bytes(&seed)
+        .verifying_key()
+        .to_bytes();
+    let pkcs8 = [&ED25519_PKCS8_PREFIX[..], &seed].concat();
+    let spki = [&ED25519_SPKI_PREFIX[..], &public_key].concat();
+
+    match options.key_format {
+        KeyFormat::PEM => Ok(GeneratedKey {
+            private_key: pem::encode_string("PRIVATE KEY", LineEnding::LF, &pkcs8)
+                .map_err(encoding_error)?
+                .into_bytes(),
+            public_key: Some(
+                pem::encode_string("PUBLIC KEY", LineEnding::LF, &spki)
+                    .map_err(encoding_error)?
+                    .into_bytes(),
+            ),
+        }),
+        KeyFormat::DER => Ok(GeneratedKey {
+            private_key: pkcs8,
+            public_key: Some(spki),
+        }),
+        KeyFormat::JWK => jwk_key(options, ed25519_parameters(&public_key, Some(&seed))),
+    }
+}
+
+/// Generates a new key, or secret, for the algorithm in `options`
+pub fn generate_key(options: &KeygenOptions) -> Result<GeneratedKey> {
+    match options.algorithm {
+        SupportedAlgorithms::HS256 => hmac_secret(options, 32),
+        SupportedAlgorithms::HS384 => hmac_secret(options, 48),
+        SupportedAlgorithms::HS512 => hmac_secret(options, 64),
+        SupportedAlgorithms::RS256
+        | SupportedAlgorithms::RS384
+        | SupportedAlgorithms::RS512
+        | SupportedAlgorithms::PS256
+        | SupportedAlgorithms::PS384
+        | SupportedAlgorithms::PS512 => rsa_key_pair(options),
+        SupportedAlgorithms::ES256 => ec_key_pair::<NistP256>(options, "P-256"),
+        SupportedAlgorithms::ES384 => ec_key_pair::<NistP384>(options, "P-384"),
+        SupportedAlgorithms::ES512 => ec_key_pair::<NistP521>(options, "P-521"),
+        SupportedAlgorithms::ES256K => ec_key_pair::<Secp256k1>(options, "secp256k1"),
+        SupportedAlgorithms::EdDSA => ed25519_key_pair(options),
+    }
+}
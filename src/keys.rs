@@ -0,0 +1,743 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error as CliError, Result};
+use crate::jwk::thumbprint;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use elliptic_curve::pkcs8::spki::SubjectPublicKeyInfoRef;
+use elliptic_curve::pkcs8::PrivateKeyInfo;
+use elliptic_curve::pkcs8::{AssociatedOid, DecodePrivateKey, DecodePublicKey, EncodePrivateKey};
+use elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use elliptic_curve::{AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey};
+use jsonwebkey::JsonWebKey;
+use jsonwebtoken as jwt;
+use jsonwebtoken::errors::{Error, ErrorKind, Result as JWTResult};
+use k256::ecdsa::signature::{Signer, Verifier};
+use k256::Secp256k1;
+use p256::NistP256;
+use p384::NistP384;
+use p521::NistP521;
+use rsa::pkcs1;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::{fs, str};
+
+/// The encoding of the key material passed as a secret
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyFormat {
+    PEM,
+    DER,
+    JWK,
+}
+
+impl KeyFormat {
+    /// Parses a `--keyformat` value. Anything unrecognised is treated as PEM.
+    pub fn from_string(format: &str) -> KeyFormat {
+        match format {
+            "der" => KeyFormat::DER,
+            "jwk" => KeyFormat::JWK,
+            _ => KeyFormat::PEM,
+        }
+    }
+
+    // Guesses the format from the extension of a secret file, falling back to PEM
+    fn from_secret_string(secret_string: &str) -> KeyFormat {
+        if !secret_string.starts_with('@') {
+            return KeyFormat::PEM;
+        }
+
+        match Path::new(secret_string).extension().and_then(OsStr::to_str) {
+            Some("pem") | Some("cer") | Some("key") => KeyFormat::PEM,
+            Some("der") | Some("pk8") => KeyFormat::DER,
+            Some("jwk") | Some("jwks") => KeyFormat::JWK,
+            _ => KeyFormat::PEM,
+        }
+    }
+}
+
+/// A key that can sign tokens with one family of algorithms
+pub struct EncodingKey(EncodingKeyKind);
+
+enum EncodingKeyKind {
+    JsonWebToken(jsonwebtoken::EncodingKey),
+    P521(p521::ecdsa::SigningKey),
+    Secp256k1(k256::ecdsa::SigningKey),
+}
+
+/// A key that can verify the signature of tokens with one family of algorithms
+pub struct DecodingKey(DecodingKeyKind);
+
+enum DecodingKeyKind {
+    JsonWebToken(jsonwebtoken::DecodingKey),
+    P521(p521::ecdsa::VerifyingKey),
+    Secp256k1(k256::ecdsa::VerifyingKey),
+}
+
+impl From<jsonwebtoken::EncodingKey> for EncodingKey {
+    fn from(key: jsonwebtoken::EncodingKey) -> Self {
+        EncodingKey(EncodingKeyKind::JsonWebToken(key))
+    }
+}
+
+fn wrong_algorithm() -> CliError {
+    CliError::UnsupportedAlgorithm("the key isn't meant for the algorithm".to_string())
+}
+
+impl EncodingKey {
+    /// Signs `message` and returns the base64url encoded signature
+    pub fn sign(&self, message: &[u8], alg: SupportedAlgorithms) -> Result<String> {
+        let signature = match (&self.0, alg) {
+            (EncodingKeyKind::JsonWebToken(key), _) => {
+                return match alg.jsonwebtoken_algorithm() {
+                    Some(alg) => Ok(jsonwebtoken::crypto::sign(message, key, alg)?),
+                    None => Err(wrong_algorithm()),
+                }
+            }
+            (EncodingKeyKind::P521(key), SupportedAlgorithms::ES512) => {
+                let signature: p521::ecdsa::Signature = key.sign(message);
+                signature.to_vec()
+            }
+            (EncodingKeyKind::Secp256k1(key), SupportedAlgorithms::ES256K) => {
+                let signature: k256::ecdsa::Signature = key.sign(message);
+                signature.to_vec()
+            }
+            _ => return Err(wrong_algorithm()),
+        };
+
+        Ok(URL_SAFE_NO_PAD.encode(signature))
+    }
+}
+
+impl DecodingKey {
+    /// Checks a base64url encoded `signature` of `message`
+    pub fn verify(
+        &self,
+        signature: &str,
+        message: &[u8],
+        alg: SupportedAlgorithms,
+    ) -> Result<bool> {
+        match (&self.0, alg) {
+            (DecodingKeyKind::JsonWebToken(key), _) => match alg.jsonwebtoken_algorithm() {
+                Some(alg) => Ok(jsonwebtoken::crypto::verify(signature, message, key, alg)?),
+                None => Err(wrong_algorithm()),
+            },
+            (DecodingKeyKind::P521(key), SupportedAlgorithms::ES512) => {
+                let signature = URL_SAFE_NO_PAD.decode(signature)?;
+
+                Ok(p521::ecdsa::Signature::from_slice(&signature)
+                    .map(|signature| key.verify(message, &signature).is_ok())
+                    .unwrap_or(false))
+            }
+            (DecodingKeyKind::Secp256k1(key), SupportedAlgorithms::ES256K) => {
+                let signature = URL_SAFE_NO_PAD.decode(signature)?;
+
+                // Other implementations don't always produce low-S signatures, which k256 rejects
+                Ok(k256::ecdsa::Signature::from_slice(&signature)
+                    .map(|signature| signature.normalize_s().unwrap_or(signature))
+                    .map(|signature| key.verify(message, &signature).is_ok())
+                    .unwrap_or(false))
+            }
+            _ => Err(wrong_algorithm()),
+        }
+    }
+}
+
+// PKCS#8 and SubjectPublicKeyInfo wrappers around raw Ed25519 keys
+pub(crate) const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+pub(crate) const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+// The object identifiers of the key types, as DER encoded in PKCS#8, SPKI and SEC1 keys
+const RSA_OIDS: [&[u8]; 2] = [
+    &[
+        0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01,
+    ],
+    &[
+        0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a,
+    ],
+];
+const ED25519_OID: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+const EC_CURVE_OIDS: [(&str, &[u8]); 4] = [
+    (
+        "P-256",
+        &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+    ),
+    ("P-384", &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22]),
+    ("P-521", &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23]),
+    ("secp256k1", &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a]),
+];
+
+/// The type of key an algorithm signs and verifies with. EC keys carry their
+/// curve, by its JWK `crv` name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum KeyType {
+    Symmetric,
+    Rsa,
+    Ec(&'static str),
+    Ed25519,
+}
+
+impl KeyType {
+    fn for_algorithm(alg: SupportedAlgorithms) -> KeyType {
+        match alg {
+            SupportedAlgorithms::HS256
+            | SupportedAlgorithms::HS384
+            | SupportedAlgorithms::HS512 => KeyType::Symmetric,
+            SupportedAlgorithms::RS256
+            | SupportedAlgorithms::RS384
+            | SupportedAlgorithms::RS512
+            | SupportedAlgorithms::PS256
+            | SupportedAlgorithms::PS384
+            | SupportedAlgorithms::PS512 => KeyType::Rsa,
+            SupportedAlgorithms::ES256 => KeyType::Ec("P-256"),
+            SupportedAlgorithms::ES384 => KeyType::Ec("P-384"),
+            SupportedAlgorithms::ES512 => KeyType::Ec("P-521"),
+            SupportedAlgorithms::ES256K => KeyType::Ec("secp256k1"),
+            SupportedAlgorithms::EdDSA => KeyType::Ed25519,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            KeyType::Symmetric => "symmetric",
+            KeyType::Rsa => "RSA",
+            KeyType::Ec(curve) => curve,
+            KeyType::Ed25519 => "Ed25519",
+        }
+    }
+
+    // Looks for the OID of a key type in a DER encoded key. PKCS#1 RSA keys don't have one.
+    fn from_der(der: &[u8]) -> Option<KeyType> {
+        let contains = |oid: &[u8]| der.windows(oid.len()).any(|window| window == oid);
+
+        if let Some((curve, _)) = EC_CURVE_OIDS.iter().find(|(_, oid)| contains(oid)) {
+            Some(KeyType::Ec(curve))
+        } else if RSA_OIDS.iter().any(|oid| contains(oid)) {
+            Some(KeyType::Rsa)
+        } else if contains(ED25519_OID) {
+            Some(KeyType::Ed25519)
+        } else {
+            None
+        }
+    }
+
+    // Works out the type of a secret, or None when it can't be told
+    pub(crate) fn from_secret(secret: &[u8], format: KeyFormat) -> Option<KeyType> {
+        match format {
+            KeyFormat::JWK => {
+                let jwk: Value = serde_json::from_slice(secret).ok()?;
+
+                match (jwk["kty"].as_str()?, jwk["crv"].as_str()) {
+                    ("oct", _) => Some(KeyType::Symmetric),
+                    ("RSA", _) => Some(KeyType::Rsa),
+                    ("EC", Some(crv)) => EC_CURVE_OIDS
+                        .iter()
+                        .find(|(curve, _)| *curve == crv)
+                        .map(|(curve, _)| KeyType::Ec(curve)),
+                    ("OKP", Some("Ed25519")) => Some(KeyType::Ed25519),
+                    _ => None,
+                }
+            }
+            KeyFormat::DER => KeyType::from_der(secret),
+            KeyFormat::PEM => match pem_contents(secret) {
+                None => Some(KeyType::Symmetric),
+                Some(("RSA PRIVATE KEY", _)) | Some(("RSA PUBLIC KEY", _)) => Some(KeyType::Rsa),
+                Some(("CERTIFICATE", _)) => None,
+                Some((_, der)) => KeyType::from_der(&der),
+            },
+        }
+    }
+}
+
+// Whether DER encoded bytes parse as one of the structures asymmetric keys come in, or are a
+// raw EC point. Raw Ed25519 keys can't be told apart from any other 32 bytes.
+fn is_asymmetric_der(der: &[u8]) -> bool {
+    PrivateKeyInfo::try_from(der).is_ok()
+        || SubjectPublicKeyInfoRef::try_from(der).is_ok()
+        || pkcs1::RsaPrivateKey::try_from(der).is_ok()
+        || pkcs1::RsaPublicKey::try_from(der).is_ok()
+        || PublicKey::<NistP256>::from_sec1_bytes(der).is_ok()
+        || PublicKey::<NistP384>::from_sec1_bytes(der).is_ok()
+        || PublicKey::<NistP521>::from_sec1_bytes(der).is_ok()
+        || PublicKey::<Secp256k1>::from_sec1_bytes(der).is_ok()
+}
+
+// Splits a PEM encoded secret into its label and DER contents
+pub(crate) fn pem_contents(secret: &[u8]) -> Option<(&str, Vec<u8>)> {
+    let text = str::from_utf8(secret).ok()?;
+    let label_start = text.find("-----BEGIN ")? + "-----BEGIN ".len();
+    let label_end = label_start + text[label_start..].find("-----")?;
+    let body_start = label_end + "-----".len();
+    let body_end = body_start + text[body_start..].find("-----END")?;
+    let body: String = text[body_start..body_end].split_whitespace().collect();
+
+    Some((
+        &text[label_start..label_end],
+        STANDARD.decode(body).unwrap_or_default(),
+    ))
+}
+
+// Refuses keys which aren't meant for `alg`, so a token can't pick an algorithm that
+// turns the key into something else, like an RSA public key into an HMAC secret.
+// Asymmetric keys whose type can't be told are left for the key parsing to reject.
+fn check_key_type(alg: SupportedAlgorithms, secret: &[u8], format: KeyFormat) -> Result<()> {
+    let expected = KeyType::for_algorithm(alg);
+
+    match KeyType::from_secret(secret, format) {
+        Some(key_type) if key_type == expected => {}
+        None if expected != KeyType::Symmetric => {}
+        // Any bytes which aren't an asymmetric key can be an HMAC secret
+        None if format == KeyFormat::DER && !is_asymmetric_der(secret) => {}
+        Some(key_type) => {
+            return Err(CliError::UnsupportedAlgorithm(format!(
+                "the {} key isn't meant for {}",
+                key_type.name(),
+                alg
+            )))
+        }
+        None => {
+            return Err(CliError::UnsupportedAlgorithm(format!(
+                "the key isn't meant for {}",
+                alg
+            )))
+        }
+    }
+
+    if format == KeyFormat::JWK {
+        let jwk: Value =
+            serde_json::from_slice(secret).map_err(|err| CliError::key(format, err.into()))?;
+
+        if let Some(jwk_alg) = jwk["alg"].as_str() {
+            if jwk_alg != alg.to_string() {
+                return Err(CliError::UnsupportedAlgorithm(format!(
+                    "the JWK is for {}, not {}",
+                    jwk_alg, alg
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn slurp_file(file_name: &str) -> Result<Vec<u8>> {
+    fs::read(file_name)
+        .map_err(|err| CliError::Io(format!("unable to read {}: {}", file_name, err)))
+}
+
+// Reads the secret, either inline or from a file when prefixed with @, and works out its format
+pub(crate) fn read_secret(
+    secret_string: &str,
+    format: Option<KeyFormat>,
+) -> Result<(Vec<u8>, KeyFormat)> {
+    let secret = if let Some(file_name) = secret_string.strip_prefix('@') {
+        slurp_file(file_name)?
+    } else {
+        secret_string.as_bytes().to_vec()
+    };
+
+    Ok((
+        secret,
+        format.unwrap_or_else(|| KeyFormat::from_secret_string(secret_string)),
+    ))
+}
+
+// jsonwebkey doesn't understand OKP keys, so Ed25519 JWKs are read by hand
+fn ed25519_jwk_parameter(jwk: &str, parameter: &str) -> JWTResult<String> {
+    let obj: Value = serde_json::from_str(jwk)?;
+
+    match (&obj["kty"], &obj["crv"], &obj[parameter]) {
+        (Value::String(kty), Value::String(crv), Value::String(value))
+            if kty == "OKP" && crv == "Ed25519" =>
+        {
+            Ok(value.clone())
+        }
+        _ => Err(Error::from(ErrorKind::InvalidKeyFormat)),
+    }
+}
+
+// jsonwebkey only knows a few values of `alg`, which has been checked against the token already
+fn rsa_jwk(jwk: &[u8]) -> JWTResult<JsonWebKey> {
+    let mut obj: Value = serde_json::from_slice(jwk)?;
+
+    if let Value::Object(members) = &mut obj {
+        members.remove("alg");
+    }
+
+    Ok(serde_json::from_value(obj)?)
+}
+
+// Reads the secret of a symmetric (`kty: oct`) JWK
+fn oct_jwk_secret(jwk: &[u8]) -> JWTResult<Vec<u8>> {
+    let obj: Value = serde_json::from_slice(jwk)?;
+
+    match (&obj["kty"], &obj["k"]) {
+        (Value::String(kty), Value::String(k)) if kty == "oct" => Ok(URL_SAFE_NO_PAD.decode(k)?),
+        _ => Err(Error::from(ErrorKind::InvalidKeyFormat)),
+    }
+}
+
+// Reads one of the parameters of an EC JWK, making sure it's for the expected curve
+fn ec_jwk_parameter(jwk: &str, curve: &str, parameter: &str) -> JWTResult<Vec<u8>> {
+    let obj: Value = serde_json::from_str(jwk)?;
+
+    match (&obj["kty"], &obj["crv"], &obj[parameter]) {
+        (Value::String(kty), Value::String(crv), Value::String(value))
+            if kty == "EC" && crv == curve =>
+        {
+            Ok(URL_SAFE_NO_PAD.decode(value)?)
+        }
+        _ => Err(Error::from(ErrorKind::InvalidKeyFormat)),
+    }
+}
+
+// Loads an EC private key for curves which jsonwebtoken doesn't support
+pub(crate) fn ec_secret_key<C>(
+    secret: &[u8],
+    format: KeyFormat,
+    curve: &str,
+) -> JWTResult<SecretKey<C>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let text = str::from_utf8(secret).map_err(|_| Error::from(ErrorKind::InvalidKeyFormat));
+    let key = match format {
+        KeyFormat::PEM => {
+            let pem = text?;
+            SecretKey::from_pkcs8_pem(pem)
+                .ok()
+                .or_else(|| SecretKey::from_sec1_pem(pem).ok())
+        }
+        KeyFormat::DER => SecretKey::from_pkcs8_der(secret)
+            .ok()
+            .or_else(|| SecretKey::from_sec1_der(secret).ok()),
+        KeyFormat::JWK => SecretKey::from_slice(&ec_jwk_parameter(text?, curve, "d")?).ok(),
+    };
+
+    key.ok_or_else(|| Error::from(ErrorKind::InvalidEcdsaKey))
+}
+
+// Loads an EC public key for curves which jsonwebtoken doesn't support. Private keys are
+// accepted too, in which case their public half is used.
+pub(crate) fn ec_public_key<C>(
+    secret: &[u8],
+    format: KeyFormat,
+    curve: &str,
+) -> JWTResult<PublicKey<C>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let text = str::from_utf8(secret).map_err(|_| Error::from(ErrorKind::InvalidKeyFormat));
+    let key = match format {
+        KeyFormat::PEM => PublicKey::from_public_key_pem(text?).ok(),
+        KeyFormat::DER => PublicKey::from_public_key_der(secret)
+            .ok()
+            .or_else(|| PublicKey::from_sec1_bytes(secret).ok()),
+        KeyFormat::JWK => {
+            let jwk = text?;
+            let point = [
+                &[0x04][..],
+                &ec_jwk_parameter(jwk, curve, "x")?,
+                &ec_jwk_parameter(jwk, curve, "y")?,
+            ]
+            .concat();
+
+            PublicKey::from_sec1_bytes(&point).ok()
+        }
+    };
+
+    match key {
+        Some(key) => Ok(key),
+        None if format != KeyFormat::JWK => {
+            ec_secret_key(secret, format, curve).map(|key: SecretKey<C>| key.public_key())
+        }
+        None => Err(Error::from(ErrorKind::InvalidEcdsaKey)),
+    }
+}
+
+// jsonwebtoken only reads PKCS#8 encoded P-256 and P-384 private keys
+fn ec_pkcs8_der<C>(secret: &[u8], format: KeyFormat, curve: &str) -> JWTResult<Vec<u8>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let key: SecretKey<C> = ec_secret_key(secret, format, curve)?;
+
+    key.to_pkcs8_der()
+        .map(|der| der.as_bytes().to_vec())
+        .map_err(|_| Error::from(ErrorKind::InvalidEcdsaKey))
+}
+
+// jsonwebtoken verifies P-256 and P-384 signatures with the uncompressed public point
+fn ec_public_point<C>(secret: &[u8], format: KeyFormat, curve: &str) -> JWTResult<Vec<u8>>
+where
+    C: CurveArithmetic + AssociatedOid,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let key: PublicKey<C> = ec_public_key(secret, format, curve)?;
+
+    Ok(key.to_encoded_point(false).as_bytes().to_vec())
+}
+
+/// Loads the key used to sign a token
+pub fn encoding_key_from_secret(
+    alg: &SupportedAlgorithms,
+    secret_string: &str,
+    format: Option<KeyFormat>,
+) -> Result<EncodingKey> {
+    let (secret, format) = read_secret(secret_string, format)?;
+
+    encoding_key(*alg, secret, format).map_err(|err| CliError::key(format, err))
+}
+
+fn encoding_key(
+    alg: SupportedAlgorithms,
+    secret: Vec<u8>,
+    format: KeyFormat,
+) -> JWTResult<EncodingKey> {
+    let key = match alg {
+        SupportedAlgorithms::HS256 | SupportedAlgorithms::HS384 | SupportedAlgorithms::HS512 => {
+            match format {
+                KeyFormat::JWK => jwt::EncodingKey::from_secret(&oct_jwk_secret(&secret)?),
+                _ => jwt::EncodingKey::from_secret(&secret),
+            }
+        }
+        SupportedAlgorithms::RS256
+        | SupportedAlgorithms::RS384
+        | SupportedAlgorithms::RS512
+        | SupportedAlgorithms::PS256
+        | SupportedAlgorithms::PS384
+        | SupportedAlgorithms::PS512 => match format {
+            KeyFormat::PEM => jwt::EncodingKey::from_rsa_pem(&secret)?,
+            KeyFormat::DER => jwt::EncodingKey::from_rsa_der(&secret),
+            KeyFormat::JWK => {
+                let jwk = rsa_jwk(&secret)?;
+                jwt::EncodingKey::from_rsa_pem(jwk.key.to_pem().as_bytes())?
+            }
+        },
+        SupportedAlgorithms::ES256 => match format {
+            KeyFormat::PEM => jwt::EncodingKey::from_ec_pem(&secret)?,
+            _ => {
+                jwt::EncodingKey::from_ec_der(&ec_pkcs8_der::<NistP256>(&secret, format, "P-256")?)
+            }
+        },
+        SupportedAlgorithms::ES384 => match format {
+            KeyFormat::PEM => jwt::EncodingKey::from_ec_pem(&secret)?,
+            _ => {
+                jwt::EncodingKey::from_ec_der(&ec_pkcs8_der::<NistP384>(&secret, format, "P-384")?)
+            }
+        },
+        SupportedAlgorithms::ES512 => {
+            let key: SecretKey<NistP521> = ec_secret_key(&secret, format, "P-521")?;
+            let key = p521::ecdsa::SigningKey::from_bytes(&key.to_bytes())
+                .map_err(|_| Error::from(ErrorKind::InvalidEcdsaKey))?;
+
+            return Ok(EncodingKey(EncodingKeyKind::P521(key)));
+        }
+        SupportedAlgorithms::ES256K => {
+            let key: SecretKey<Secp256k1> = ec_secret_key(&secret, format, "secp256k1")?;
+
+            return Ok(EncodingKey(EncodingKeyKind::Secp256k1(key.into())));
+        }
+        SupportedAlgorithms::EdDSA => match format {
+            KeyFormat::PEM => jwt::EncodingKey::from_ed_pem(&secret)?,
+            KeyFormat::DER => jwt::EncodingKey::from_ed_der(&secret),
+            KeyFormat::JWK => {
+                let d = ed25519_jwk_parameter(&String::from_utf8(secret)?, "d")?;
+                let pkcs8 = [&ED25519_PKCS8_PREFIX[..], &URL_SAFE_NO_PAD.decode(d)?].concat();
+                jwt::EncodingKey::from_ed_der(&pkcs8)
+            }
+        },
+    };
+
+    Ok(key.into())
+}
+
+/// One of the keys a token can be verified with
+pub struct VerificationKey {
+    /// The kid of the JWK the key came from, or its thumbprint when it doesn't have one
+    pub kid: Option<String>,
+    pub key: DecodingKey,
+}
+
+// Whether a JWK in a set may verify tokens signed with `alg`
+fn jwk_suits(alg: SupportedAlgorithms, jwk: &Value) -> bool {
+    let usable = match (&jwk["use"], &jwk["key_ops"]) {
+        (Value::Null, Value::Null) => true,
+        (Value::String(key_use), Value::Null) => key_use == "sig",
+        (Value::Null, Value::Array(key_ops)) => key_ops.iter().any(|op| op == "verify"),
+        _ => false,
+    };
+
+    usable
+        && serde_json::to_vec(jwk)
+            .map(|jwk| check_key_type(alg, &jwk, KeyFormat::JWK).is_ok())
+            .unwrap_or(false)
+}
+
+// The JWKs which could verify a token. A set is narrowed down to the key with `kid` when the
+// token has one, and otherwise to every key whose type, `alg` and `use` suit `alg`.
+pub(crate) fn candidate_jwks(
+    alg: SupportedAlgorithms,
+    secret: &[u8],
+    kid: Option<&String>,
+) -> Result<Vec<Value>> {
+    let jwk: Value =
+        serde_json::from_slice(secret).map_err(|err| CliError::key(KeyFormat::JWK, err.into()))?;
+    let keys = match &jwk["keys"] {
+        Value::Array(keys) => keys,
+        _ => return Ok(vec![jwk]),
+    };
+
+    match kid {
+        Some(kid) => match keys.iter().find(|key| key["kid"] == kid.as_str()) {
+            Some(key) => Ok(vec![key.clone()]),
+            None => Err(CliError::KidNotFound(kid.clone())),
+        },
+        None => Ok(keys
+            .iter()
+            .filter(|key| jwk_suits(alg, key))
+            .cloned()
+            .collect()),
+    }
+}
+
+/// Loads the keys which could verify a token. When the secret is a JWK set, the
+/// key matching `kid` is selected, or every key suiting `alg` when there's no kid.
+pub fn decoding_keys_from_secret(
+    alg: &SupportedAlgorithms,
+    secret_string: &str,
+    format: Option<KeyFormat>,
+    kid: Option<&String>,
+) -> Result<Vec<VerificationKey>> {
+    let (secret, format) = read_secret(secret_string, format)?;
+
+    if format != KeyFormat::JWK {
+        return Ok(vec![VerificationKey {
+            kid: None,
+            key: decoding_key(*alg, &secret, format)?,
+        }]);
+    }
+
+    candidate_jwks(*alg, &secret, kid)?
+        .into_iter()
+        .map(|jwk| {
+            Ok(VerificationKey {
+                kid: jwk["kid"]
+                    .as_str()
+                    .map(String::from)
+                    .or_else(|| thumbprint(&jwk).ok()),
+                key: decoding_key(*alg, &serde_json::to_vec(&jwk)?, format)?,
+            })
+        })
+        .collect()
+}
+
+/// Loads the key used to verify a token. When the secret is a JWK set, the
+/// key matching `kid` is selected, or the first key suiting `alg` when there's no kid.
+pub fn decoding_key_from_secret(
+    alg: &SupportedAlgorithms,
+    secret_string: &str,
+    format: Option<KeyFormat>,
+    kid: Option<&String>,
+) -> Result<DecodingKey> {
+    decoding_keys_from_secret(alg, secret_string, format, kid)?
+        .into_iter()
+        .next()
+        .map(|key| key.key)
+        .ok_or_else(|| {
+            CliError::UnsupportedAlgorithm(format!(
+                "none of the keys in the JWK Set is for {}",
+                alg
+            ))
+        })
+}
+
+// Loads a single key, which has already been picked out of a set
+pub(crate) fn decoding_key(
+    alg: SupportedAlgorithms,
+    secret: &[u8],
+    format: KeyFormat,
+) -> Result<DecodingKey> {
+    check_key_type(alg, secret, format)?;
+
+    parse_decoding_key(alg, secret, format).map_err(|err| CliError::key(format, err))
+}
+
+fn parse_decoding_key(
+    alg: SupportedAlgorithms,
+    secret: &[u8],
+    format: KeyFormat,
+) -> JWTResult<DecodingKey> {
+    let key = match alg {
+        SupportedAlgorithms::HS256 | SupportedAlgorithms::HS384 | SupportedAlgorithms::HS512 => {
+            match format {
+                KeyFormat::JWK => jwt::DecodingKey::from_secret(&oct_jwk_secret(secret)?),
+                _ => jwt::DecodingKey::from_secret(secret),
+            }
+        }
+        SupportedAlgorithms::RS256
+        | SupportedAlgorithms::RS384
+        | SupportedAlgorithms::RS512
+        | SupportedAlgorithms::PS256
+        | SupportedAlgorithms::PS384
+        | SupportedAlgorithms::PS512 => match format {
+            KeyFormat::PEM => jwt::DecodingKey::from_rsa_pem(secret)?,
+            KeyFormat::DER => jwt::DecodingKey::from_rsa_der(secret),
+            KeyFormat::JWK => {
+                let jwk = rsa_jwk(secret)?;
+                jwt::DecodingKey::from_rsa_pem(jwk.key.to_pem().as_bytes())?
+            }
+        },
+        SupportedAlgorithms::ES256 => match format {
+            KeyFormat::PEM => jwt::DecodingKey::from_ec_pem(secret)?,
+            _ => jwt::DecodingKey::from_ec_der(&ec_public_point::<NistP256>(
+                secret, format, "P-256",
+            )?),
+        },
+        SupportedAlgorithms::ES384 => match format {
+            KeyFormat::PEM => jwt::DecodingKey::from_ec_pem(secret)?,
+            _ => jwt::DecodingKey::from_ec_der(&ec_public_point::<NistP384>(
+                secret, format, "P-384",
+            )?),
+        },
+        SupportedAlgorithms::ES512 => {
+            let key: PublicKey<NistP521> = ec_public_key(secret, format, "P-521")?;
+            let key = p521::ecdsa::VerifyingKey::from_affine(*key.as_affine())
+                .map_err(|_| Error::from(ErrorKind::InvalidEcdsaKey))?;
+
+            return Ok(DecodingKey(DecodingKeyKind::P521(key)));
+        }
+        SupportedAlgorithms::ES256K => {
+            let key: PublicKey<Secp256k1> = ec_public_key(secret, format, "secp256k1")?;
+
+            return Ok(DecodingKey(DecodingKeyKind::Secp256k1(key.into())));
+        }
+        SupportedAlgorithms::EdDSA => match format {
+            KeyFormat::PEM => jwt::DecodingKey::from_ed_pem(secret)?,
+            KeyFormat::DER => jwt::DecodingKey::from_ed_der(
+                secret
+                    .strip_prefix(&ED25519_SPKI_PREFIX[..])
+                    .unwrap_or(secret),
+            ),
+            KeyFormat::JWK => {
+                let jwk = str::from_utf8(secret).map_err(|_| ErrorKind::InvalidKeyFormat)?;
+                let x = ed25519_jwk_parameter(jwk, "x")?;
+                jwt::DecodingKey::from_ed_components(&x)?
+            }
+        },
+    };
+
+    Ok(DecodingKey(DecodingKeyKind::JsonWebToken(key)))
+}
@@ -0,0 +1,58 @@
+//! The library behind the `jwt` command line tool. It generates and loads keys
+//! in PEM, DER or JWK format, builds claims and encodes or decodes tokens with them.
+//! Tokens can also be verified with JWK Sets fetched over HTTP, and signed with
+//! the certificate chain of their key in the header.
+
+mod algorithm;
+mod decode;
+mod encode;
+mod error;
+mod jwe;
+mod jwk;
+mod jwks;
+mod jws;
+mod keygen;
+mod keys;
+mod payload;
+mod policy;
+mod query;
+mod remote;
+mod template;
+mod validation;
+mod x509;
+
+pub use algorithm::SupportedAlgorithms;
+pub use decode::{
+    decode_token, DecodeOptions, DecodeOutcome, LayerHeader, SignatureResult, Timing, TokenOutput,
+    Verification, VerificationError,
+};
+pub use encode::{create_header, encode_token, EncodeOptions};
+pub use error::{Error, ValidationError};
+pub use jwe::{
+    decrypt, encrypt, insecure_decode_jwe_header, is_jwe, ContentEncryptionAlgorithm, JweHeader,
+    KeyManagementAlgorithm,
+};
+pub use jwk::{jwk_from_key, public_jwk, thumbprint};
+pub use jwks::JwkSet;
+pub use jws::{
+    attach_payload, decode, detach_payload, encode, encode_json, insecure_decode,
+    insecure_decode_payload, is_detached, is_json_serialization, sign_detached, sign_payload,
+    split_json_serialization, verify_signature, Header, JsonSignature, Serialization, TokenData,
+};
+pub use keygen::{generate_key, GeneratedKey, KeygenOptions};
+pub use keys::{
+    decoding_key_from_secret, decoding_keys_from_secret, encoding_key_from_secret, DecodingKey,
+    EncodingKey, KeyFormat, VerificationKey,
+};
+pub use payload::{
+    claim_description, describe_date, parse_claim_path, read_payload_file, PathSegment, Payload,
+    PayloadItem,
+};
+pub use policy::{ClaimRule, ClaimType, KeySources, Policy, PolicyViolation};
+pub use query::{parse_query, query};
+pub use remote::{fetch_jwks, JwksSource};
+pub use template::render_template;
+pub use validation::{validate, Validation};
+pub use x509::{
+    add_cert_chain, certificate_jwk, read_cert_chain, validate_cert_chain, ChainCertificate,
+};
@@ -1,162 +1,48 @@
 use atty::Stream;
-use chrono::{TimeZone, Utc};
-use clap::{arg_enum, crate_authors, crate_version, App, Arg, ArgMatches, SubCommand};
-use jsonwebtoken::errors::{Error, ErrorKind, Result as JWTResult};
-use jsonwebtoken::{
-    dangerous_insecure_decode, decode, encode, Algorithm, DecodingKey, EncodingKey, Header,
-    TokenData, Validation,
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use chrono::Utc;
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use jwt_cli::{
+    parse_claim_path, ContentEncryptionAlgorithm, DecodeOptions, DecodeOutcome, EncodeOptions,
+    Error, GeneratedKey, JwkSet, JwksSource, KeyFormat, KeyManagementAlgorithm, KeygenOptions,
+    Payload, PayloadItem, Policy, Serialization, SupportedAlgorithms, Timing, TokenOutput,
+    ValidationError,
 };
-
-use serde_derive::{Deserialize, Serialize};
-use serde_json::{from_str, to_string_pretty, Value};
+use serde_json::to_string_pretty;
+use serde_json::Value;
 use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::{fs, io, str};
-use std::path::Path;
-use std::ffi::OsStr;
-
-use jsonwebkey::{JsonWebKey};
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-struct PayloadItem(String, Value);
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-struct Payload(BTreeMap<String, Value>);
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-struct TokenOutput {
-    header: Header,
-    payload: Payload,
-}
-
-arg_enum! {
-    #[allow(clippy::clippy::upper_case_acronyms)]
-    #[derive(Debug, PartialEq)]
-    enum SupportedAlgorithms {
-        HS256,
-        HS384,
-        HS512,
-        RS256,
-        RS384,
-        RS512,
-        PS256,
-        PS384,
-        PS512,
-        ES256,
-        ES384,
-    }
-}
-
-arg_enum! {
-    #[allow(clippy::clippy::upper_case_acronyms)]
-    enum SupportedTypes {
-        JWT
-    }
-}
-
-enum KeyFormat {
-    PEM,
-    DER,
-    JWK
-}
 
 #[derive(Debug, PartialEq)]
 enum OutputFormat {
     Text,
     Json,
+    Yaml,
+    Toml,
+    Table,
+    Raw,
+    Header,
+    Payload,
+    Query(String),
 }
 
-impl PayloadItem {
-    fn from_string(val: Option<&str>) -> Option<PayloadItem> {
-        val.map(|item| PayloadItem::split_payload_item(item))
-    }
-
-    fn from_string_with_name(val: Option<&str>, name: &str) -> Option<PayloadItem> {
-        match val {
-            Some(value) => match from_str(value) {
-                Ok(json_value) => Some(PayloadItem(name.to_string(), json_value)),
-                Err(_) => match from_str(format!("\"{}\"", value).as_str()) {
-                    Ok(json_value) => Some(PayloadItem(name.to_string(), json_value)),
-                    Err(_) => None,
-                },
-            },
-            _ => None,
-        }
-    }
-
-    // If the value is defined as systemd.time, converts the defined duration into a UNIX timestamp
-    fn from_timestamp_with_name(val: Option<&str>, name: &str, now: i64) -> Option<PayloadItem> {
-        if let Some(timestamp) = val {
-            if timestamp.parse::<u64>().is_err() {
-                let duration = parse_duration::parse(timestamp);
-                if let Ok(parsed_duration) = duration {
-                    let seconds = parsed_duration.as_secs() + now as u64;
-                    return PayloadItem::from_string_with_name(Some(&seconds.to_string()), name);
-                }
-            }
-        }
-
-        PayloadItem::from_string_with_name(val, name)
-    }
-
-    fn split_payload_item(p: &str) -> PayloadItem {
-        let split: Vec<&str> = p.split('=').collect();
-        let (name, value) = (split[0], split[1]);
-        let payload_item = PayloadItem::from_string_with_name(Some(value), name);
-
-        payload_item.unwrap()
-    }
-}
-
-impl Payload {
-    fn from_payloads(payloads: Vec<PayloadItem>) -> Payload {
-        let mut payload = BTreeMap::new();
-
-        for PayloadItem(k, v) in payloads {
-            payload.insert(k, v);
-        }
-
-        Payload(payload)
-    }
-
-    fn convert_timestamps(&mut self) {
-        let timestamp_claims: Vec<String> = vec!["iat".into(), "nbf".into(), "exp".into()];
-
-        for (key, value) in self.0.iter_mut() {
-            if timestamp_claims.contains(key) && value.is_number() {
-                *value = match value.as_i64() {
-                    Some(timestamp) => Utc.timestamp(timestamp, 0).to_rfc3339().into(),
-                    None => value.clone(),
-                }
-            }
-        }
-    }
-}
-
-impl SupportedAlgorithms {
-    fn from_string(alg: &str) -> SupportedAlgorithms {
-        match alg {
-            "HS256" => SupportedAlgorithms::HS256,
-            "HS384" => SupportedAlgorithms::HS384,
-            "HS512" => SupportedAlgorithms::HS512,
-            "RS256" => SupportedAlgorithms::RS256,
-            "RS384" => SupportedAlgorithms::RS384,
-            "RS512" => SupportedAlgorithms::RS512,
-            "PS256" => SupportedAlgorithms::PS256,
-            "PS384" => SupportedAlgorithms::PS384,
-            "PS512" => SupportedAlgorithms::PS512,
-            "ES256" => SupportedAlgorithms::ES256,
-            "ES384" => SupportedAlgorithms::ES384,
-            _ => SupportedAlgorithms::HS256,
-        }
-    }
-}
-
-impl TokenOutput {
-    fn new(data: TokenData<Payload>) -> Self {
-        TokenOutput {
-            header: data.header,
-            payload: data.claims,
+impl OutputFormat {
+    fn from_string(format: &str) -> OutputFormat {
+        match format {
+            "json" => OutputFormat::Json,
+            "yaml" => OutputFormat::Yaml,
+            "toml" => OutputFormat::Toml,
+            "table" => OutputFormat::Table,
+            "raw" => OutputFormat::Raw,
+            "header" => OutputFormat::Header,
+            "payload" => OutputFormat::Payload,
+            _ => OutputFormat::Text,
         }
     }
 }
@@ -171,33 +57,70 @@ fn config_options<'a, 'b>() -> App<'a, 'b> {
                 .about("Encode new JWTs")
                 .arg(
                     Arg::with_name("algorithm")
-                        .help("the algorithm to use for signing the JWT")
+                        .help("the algorithm to use for signing the JWT. Repeat it to give each secret its own, in the same order")
                         .takes_value(true)
                         .long("alg")
                         .short("A")
+                        .multiple(true)
+                        .number_of_values(1)
                         .possible_values(&SupportedAlgorithms::variants())
                         .default_value("HS256"),
                 ).arg(
                     Arg::with_name("kid")
-                        .help("the kid to place in the header")
+                        .help("the kid to place in the header. Repeat it to give each secret its own, in the same order")
                         .takes_value(true)
                         .long("kid")
-                        .short("k"),
+                        .short("k")
+                        .multiple(true)
+                        .number_of_values(1),
                 ).arg(
                     Arg::with_name("type")
-                        .help("the type of token being encoded")
+                        .help("the type of token being encoded, such as at+jwt. Default: JWT")
                         .takes_value(true)
                         .long("typ")
-                        .short("t")
-                        .possible_values(&SupportedTypes::variants()),
+                        .short("t"),
+                ).arg(
+                    Arg::with_name("content_type")
+                        .help("the content type to place in the header")
+                        .takes_value(true)
+                        .long("cty"),
+                ).arg(
+                    Arg::with_name("header")
+                        .help("a parameter to add to the header, as key=value for a string or key:=json for any JSON value")
+                        .number_of_values(1)
+                        .multiple(true)
+                        .takes_value(true)
+                        .long("header")
+                        .short("H")
+                        .validator(is_header_param),
+                ).arg(
+                    Arg::with_name("header_json")
+                        .help("a JSON object whose fields are added to the header")
+                        .takes_value(true)
+                        .long("header-json"),
                 ).arg(
                     Arg::with_name("json")
-                        .help("the json payload to encode")
+                        .help("the json payload to encode. Can be - to read it from STDIN, or prefixed with @ to read it from a JSON, YAML (.yaml, .yml) or TOML (.toml) file")
                         .index(1)
                         .required(false),
                 ).arg(
+                    Arg::with_name("template")
+                        .help("a JSON file with the payload to encode, whose ${var} placeholders are filled in from --var, or by the ${now}, ${now+1h}, ${uuid} and ${env:NAME} helpers")
+                        .takes_value(true)
+                        .long("template")
+                        .conflicts_with("json"),
+                ).arg(
+                    Arg::with_name("var")
+                        .help("a name=value variable for the template")
+                        .takes_value(true)
+                        .long("var")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("template")
+                        .validator(is_template_var),
+              ).arg(
                     Arg::with_name("payload")
-                        .help("a key=value pair to add to the payload")
+                        .help("a claim to add to the payload, as key=value for a string or key:=json for any JSON value. The key can be a path like user.roles[] to nest it in objects and arrays")
                         .number_of_values(1)
                         .multiple(true)
                         .takes_value(true)
@@ -248,11 +171,30 @@ fn config_options<'a, 'b>() -> App<'a, 'b> {
                         .long("no-iat")
                 ).arg(
                     Arg::with_name("secret")
-                        .help("the secret to sign the JWT with. Can be prefixed with @ to read from a file")
+                        .help("the secret to sign the JWT with. Can be prefixed with @ to read from a file, and repeated to sign with several secrets in the general JSON serialization")
                         .takes_value(true)
                         .long("secret")
                         .short("S")
+                        .multiple(true)
+                        .number_of_values(1)
                         .required(true),
+                ).arg(
+                    Arg::with_name("serialization")
+                        .help("how to serialize the JWT")
+                        .takes_value(true)
+                        .long("serialization")
+                        .possible_values(&Serialization::variants())
+                        .default_value("compact"),
+                ).arg(
+                    Arg::with_name("detached")
+                        .help("leave the payload out of the JWT, as header..signature")
+                        .long("detached"),
+                ).arg(
+                    Arg::with_name("payload_file")
+                        .help("a file to sign as it is instead of the claims, unencoded as RFC 7797 allows with `b64: false`")
+                        .takes_value(true)
+                        .long("payload-file")
+                        .conflicts_with_all(&["json", "template", "payload", "expires", "not_before", "issuer", "subject", "audience", "jwt_id"]),
                 ).arg(
                     Arg::with_name("keyformat")
                         .help("the format of the secret param or file: pem|der|jwk are supported. Default: pem")
@@ -260,6 +202,36 @@ fn config_options<'a, 'b>() -> App<'a, 'b> {
                         .long("keyformat")
                         .short("f")
                         .required(false),
+                ).arg(
+                    Arg::with_name("cert_chain")
+                        .help("a PEM file with the certificate chain of the signing key, leaf first, to put in the header as x5c with the x5t and x5t#S256 thumbprints of the leaf")
+                        .takes_value(true)
+                        .long("cert-chain"),
+              ).arg(
+                    Arg::with_name("encrypt")
+                        .help("the key to encrypt the signed JWT for, making it a nested JWT in a JWE. Can be prefixed with @ to read from a file")
+                        .takes_value(true)
+                        .long("encrypt"),
+                ).arg(
+                    Arg::with_name("encrypt_keyformat")
+                        .help("the format of the encryption key: pem|der|jwk are supported. Default: pem")
+                        .takes_value(true)
+                        .long("encrypt-keyformat")
+                        .requires("encrypt"),
+                ).arg(
+                    Arg::with_name("encrypt_alg")
+                        .help("the algorithm to encrypt the content key with. Default: RSA-OAEP-256, ECDH-ES or A*KW, depending on the key")
+                        .takes_value(true)
+                        .long("encrypt-alg")
+                        .possible_values(&KeyManagementAlgorithm::variants())
+                        .requires("encrypt"),
+                ).arg(
+                    Arg::with_name("enc")
+                        .help("the algorithm to encrypt the signed JWT with")
+                        .takes_value(true)
+                        .long("enc")
+                        .possible_values(&ContentEncryptionAlgorithm::variants())
+                        .default_value("A256GCM"),
                 ),
         ).subcommand(
             SubCommand::with_name("decode")
@@ -271,33 +243,121 @@ fn config_options<'a, 'b>() -> App<'a, 'b> {
                         .required(true),
                 ).arg(
                     Arg::with_name("algorithm")
-                        .help("the algorithm to use for signing the JWT")
+                        .help("the algorithm the JWT must be signed with. Default: the alg in the JWT header")
                         .takes_value(true)
                         .long("alg")
                         .short("A")
-                        .possible_values(&SupportedAlgorithms::variants())
-                        .default_value("HS256"),
+                        .possible_values(&SupportedAlgorithms::variants()),
                 ).arg(
                     Arg::with_name("iso_dates")
-                        .help("display unix timestamps as ISO 8601 dates")
+                        .help("display the unix timestamps of date claims as ISO 8601 dates, in every output format")
                         .takes_value(false)
                         .long("iso8601")
+                ).arg(
+                    Arg::with_name("date_claim")
+                        .help("a claim which holds a unix timestamp, besides exp, nbf, iat, auth_time and updated_at, to annotate in the text output and convert with --iso8601")
+                        .takes_value(true)
+                        .long("date-claim")
+                        .multiple(true)
+                        .number_of_values(1),
                 ).arg(
                     Arg::with_name("secret")
-                        .help("the secret to validate the JWT with. Can be prefixed with @ to read from a file")
+                        .help("the secret to validate the JWT with, or the key to decrypt it with when it's encrypted. Only used for the outermost layer of nested JWTs. Can be prefixed with @ to read from a file")
                         .takes_value(true)
                         .long("secret")
                         .short("S")
                         .default_value(""),
+                ).arg(
+                    Arg::with_name("inner_secret")
+                        .help("the secret to validate, or key to decrypt, the JWTs nested inside the outermost one with. Can be prefixed with @ to read from a file")
+                        .takes_value(true)
+                        .long("inner-secret"),
+                ).arg(
+                    Arg::with_name("inner_keyformat")
+                        .help("the format of the inner secret param or file: pem|der|jwk are supported. Default: pem")
+                        .takes_value(true)
+                        .long("inner-keyformat")
+                        .requires("inner_secret"),
                 ).arg(
                     Arg::with_name("json")
                         .help("render decoded JWT as JSON")
                         .long("json")
                         .short("j"),
+                ).arg(
+                    Arg::with_name("output")
+                        .help("how to render the decoded JWT: text, json, yaml or toml, a table of the claims, the raw segments, or only the header or payload as compact JSON. Default: text")
+                        .takes_value(true)
+                        .long("output")
+                        .short("o")
+                        .possible_values(&["text", "json", "yaml", "toml", "table", "raw", "header", "payload"])
+                        .conflicts_with("json"),
+                ).arg(
+                    Arg::with_name("query")
+                        .help("a path like payload.sub or payload.roles[0] to a value in the --json output, which is printed on its own, as text when it's a string and otherwise as compact JSON. It can start with $. or . and exits with 6 when there's nothing there")
+                        .takes_value(true)
+                        .long("query")
+                        .short("q")
+                        .conflicts_with_all(&["json", "output"])
+                        .validator(is_query),
                 ).arg(
                     Arg::with_name("ignore_exp")
                         .help("Ignore token expiration date (`exp` claim) during validation.")
                         .long("ignore-exp")
+                ).arg(
+                    Arg::with_name("leeway")
+                        .help("the clock skew allowed when validating `exp`, `nbf` and `iat`, in seconds or as a systemd.time string. Default: 1000")
+                        .takes_value(true)
+                        .long("leeway")
+                        .validator(is_duration),
+                ).arg(
+                    Arg::with_name("expect_iss")
+                        .help("the issuer the `iss` claim must be")
+                        .takes_value(true)
+                        .long("expect-iss"),
+                ).arg(
+                    Arg::with_name("expect_aud")
+                        .help("an audience the `aud` claim may include. The token is valid when it has any of them")
+                        .takes_value(true)
+                        .long("expect-aud")
+                        .multiple(true)
+                        .number_of_values(1),
+                ).arg(
+                    Arg::with_name("expect_sub")
+                        .help("the subject the `sub` claim must be")
+                        .takes_value(true)
+                        .long("expect-sub"),
+                ).arg(
+                    Arg::with_name("require_claim")
+                        .help("a claim which has to be present in the token")
+                        .takes_value(true)
+                        .long("require-claim")
+                        .multiple(true)
+                        .number_of_values(1),
+                ).arg(
+                    Arg::with_name("validate_nbf")
+                        .help("reject tokens whose `nbf` claim is in the future")
+                        .long("validate-nbf"),
+                ).arg(
+                    Arg::with_name("detached_payload")
+                        .help("the file with the payload of a JWT signed with it detached, as header..signature")
+                        .takes_value(true)
+                        .long("detached-payload"),
+                ).arg(
+                    Arg::with_name("trust_anchor")
+                        .help("a PEM file with the root certificates the x5c chain in the JWT header, or in its JWK, has to lead to. The JWT is verified with the key of the chain's leaf certificate")
+                        .takes_value(true)
+                        .long("trust-anchor"),
+              ).arg(
+                    Arg::with_name("policy")
+                        .help("a TOML file with the rules the JWT must follow, and where to find the keys to validate it with when no secret is given")
+                        .takes_value(true)
+                        .long("policy"),
+                ).arg(
+                    Arg::with_name("max_age")
+                        .help("reject tokens issued longer ago than this, according to their `iat` claim, in seconds or as a systemd.time string")
+                        .takes_value(true)
+                        .long("max-age")
+                        .validator(is_duration),
                 ).arg(
                     Arg::with_name("keyformat")
                         .help("the format of the secret param or file: pem|der|jwk are supported. Default: pem")
@@ -305,10 +365,145 @@ fn config_options<'a, 'b>() -> App<'a, 'b> {
                         .long("keyformat")
                         .short("f")
                         .required(false),
+                ).arg(
+                    Arg::with_name("jwks_url")
+                        .help("the URL of a JWK Set to validate the JWT with, instead of the secret")
+                        .takes_value(true)
+                        .long("jwks-url")
+                        .conflicts_with("issuer_discovery"),
+                ).arg(
+                    Arg::with_name("issuer_discovery")
+                        .help("an OpenID Connect issuer whose discovery document leads to the JWK Set to validate the JWT with")
+                        .takes_value(true)
+                        .long("issuer-discovery"),
+                ).arg(
+                    Arg::with_name("jwks_cache_dir")
+                        .help("the directory fetched JWK Sets are cached in, for as long as their Cache-Control header allows. Default: the user's cache directory")
+                        .takes_value(true)
+                        .long("jwks-cache-dir"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("keygen")
+                .about("Generate a key pair, or an HMAC secret, to sign and verify JWTs with")
+                .arg(
+                    Arg::with_name("algorithm")
+                        .help("the algorithm the key will be used for")
+                        .takes_value(true)
+                        .long("alg")
+                        .short("A")
+                        .possible_values(&SupportedAlgorithms::variants())
+                        .default_value("HS256"),
+                ).arg(
+                    Arg::with_name("keyformat")
+                        .help("the format of the generated keys. HMAC secrets are plain text unless jwk is used")
+                        .takes_value(true)
+                        .long("keyformat")
+                        .short("f")
+                        .possible_values(&["pem", "der", "jwk"])
+                        .default_value("pem"),
+                ).arg(
+                    Arg::with_name("kid")
+                        .help("the kid to place in JWKs. Default: the RFC 7638 thumbprint of the key")
+                        .takes_value(true)
+                        .long("kid")
+                        .short("k"),
+                ).arg(
+                    Arg::with_name("bits")
+                        .help("the size of RSA keys, in bits")
+                        .takes_value(true)
+                        .long("bits")
+                        .default_value("2048")
+                        .validator(is_rsa_key_size),
+                ).arg(
+                    Arg::with_name("out")
+                        .help("the file to write the private key or secret to. Default: stdout")
+                        .takes_value(true)
+                        .long("out")
+                        .short("o")
+                        .required_if("keyformat", "der"),
+                ).arg(
+                    Arg::with_name("public_out")
+                        .help("the file to write the public key to. Default: stdout")
+                        .takes_value(true)
+                        .long("public-out")
+                        .short("p")
+                        .required_if("keyformat", "der"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("jwks")
+                .about("Build and manage JWK Sets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add a key to a JWK Set, creating the set if it doesn't exist")
+                        .arg(jwks_file_arg())
+                        .arg(
+                            Arg::with_name("key")
+                                .help("the key to add, in PEM, DER or JWK. Can be prefixed with @ to read from a file")
+                                .index(2)
+                                .required(true),
+                        ).arg(
+                            Arg::with_name("keyformat")
+                                .help("the format of the key param or file: pem|der|jwk are supported. Default: pem")
+                                .takes_value(true)
+                                .long("keyformat")
+                                .short("f"),
+                        ).arg(
+                            Arg::with_name("kid")
+                                .help("the kid of the key. Default: the RFC 7638 thumbprint of the key")
+                                .takes_value(true)
+                                .long("kid")
+                                .short("k"),
+                        ).arg(
+                            Arg::with_name("algorithm")
+                                .help("the algorithm the key is meant for")
+                                .takes_value(true)
+                                .long("alg")
+                                .short("A")
+                                .possible_values(&SupportedAlgorithms::variants()),
+                        ).arg(
+                            Arg::with_name("use")
+                                .help("what the key is meant for")
+                                .takes_value(true)
+                                .long("use")
+                                .possible_values(&["sig", "enc"]),
+                        ),
+                ).subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove a key from a JWK Set")
+                        .arg(jwks_file_arg())
+                        .arg(
+                            Arg::with_name("kid")
+                                .help("the kid of the key to remove")
+                                .index(2)
+                                .required(true),
+                        ),
+                ).subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the kid, kty, alg and use of the keys in a JWK Set")
+                        .arg(jwks_file_arg()),
+                ).subcommand(
+                    SubCommand::with_name("public")
+                        .about("Print a JWK Set without its private keys, ready to publish")
+                        .arg(jwks_file_arg())
+                        .arg(
+                            Arg::with_name("out")
+                                .help("the file to write the public set to. Default: stdout")
+                                .takes_value(true)
+                                .long("out")
+                                .short("o"),
+                        ),
                 ),
         )
 }
 
+fn jwks_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("jwks")
+        .help("the JWK Set file")
+        .index(1)
+        .required(true)
+}
+
 fn is_timestamp_or_duration(val: String) -> Result<(), String> {
     match val.parse::<i64>() {
         Ok(_) => Ok(()),
@@ -321,337 +516,471 @@ fn is_timestamp_or_duration(val: String) -> Result<(), String> {
     }
 }
 
+fn is_duration(val: String) -> Result<(), String> {
+    match parse_duration::parse(&val) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from(
+            "must be a number of seconds or systemd.time string",
+        )),
+    }
+}
+
+fn parse_seconds(val: &str) -> u64 {
+    parse_duration::parse(val).unwrap().as_secs()
+}
+
 fn is_payload_item(val: String) -> Result<(), String> {
-    match val.split('=').count() {
-        2 => Ok(()),
+    PayloadItem::parse(&val)
+        .and_then(|PayloadItem(path, _)| parse_claim_path(&path))
+        .map(|_| ())
+}
+
+fn is_query(val: String) -> Result<(), String> {
+    jwt_cli::parse_query(&val).map(|_| ())
+}
+
+fn is_template_var(val: String) -> Result<(), String> {
+    match val.split_once('=') {
+        Some((name, _)) if !name.is_empty() => Ok(()),
         _ => Err(String::from(
-            "payloads must have a key and value in the form key=value",
+            "variables must have a name and value in the form name=value",
         )),
     }
 }
 
-fn warn_unsupported(matches: &ArgMatches) {
-    if matches.value_of("type").is_some() {
-        println!("Sorry, `typ` isn't supported quite yet!");
+fn is_header_param(val: String) -> Result<(), String> {
+    PayloadItem::parse(&val).map(|_| ())
+}
+
+fn is_rsa_key_size(val: String) -> Result<(), String> {
+    match val.parse::<usize>() {
+        Ok(bits) if bits >= 2048 => Ok(()),
+        _ => Err(String::from("RSA keys must be at least 2048 bits long")),
     }
 }
 
-fn translate_algorithm(alg: SupportedAlgorithms) -> Algorithm {
-    match alg {
-        SupportedAlgorithms::HS256 => Algorithm::HS256,
-        SupportedAlgorithms::HS384 => Algorithm::HS384,
-        SupportedAlgorithms::HS512 => Algorithm::HS512,
-        SupportedAlgorithms::RS256 => Algorithm::RS256,
-        SupportedAlgorithms::RS384 => Algorithm::RS384,
-        SupportedAlgorithms::RS512 => Algorithm::RS512,
-        SupportedAlgorithms::PS256 => Algorithm::PS256,
-        SupportedAlgorithms::PS384 => Algorithm::PS384,
-        SupportedAlgorithms::PS512 => Algorithm::PS512,
-        SupportedAlgorithms::ES256 => Algorithm::ES256,
-        SupportedAlgorithms::ES384 => Algorithm::ES384,
+fn read_stdin_if_dash(value: &str) -> Result<String, Error> {
+    match value {
+        "-" => read_text(io::stdin(), "STDIN"),
+        _ => Ok(String::from(value)),
     }
 }
 
-fn create_header(alg: Algorithm, kid: Option<&str>) -> Header {
-    let mut header = Header::new(alg);
+fn read_text(mut reader: impl Read, name: &str) -> Result<String, Error> {
+    let mut buffer = String::new();
 
-    header.kid = kid.map(str::to_string);
+    reader
+        .read_to_string(&mut buffer)
+        .map_err(|err| Error::Io(format!("unable to read {}: {}", name, err)))?;
 
-    header
+    Ok(buffer)
 }
 
-fn slurp_file(file_name: &str) -> Vec<u8> {
-    fs::read(file_name).unwrap_or_else(|_| panic!("Unable to read file {}", file_name))
-}
+fn encode_token(matches: &ArgMatches) -> Result<String, Error> {
+    let secrets: Vec<&str> = matches.values_of("secret").unwrap().collect();
+    let algorithms = matches
+        .values_of("algorithm")
+        .unwrap()
+        .map(SupportedAlgorithms::from_string)
+        .collect::<Result<Vec<_>, Error>>()?;
+    let kids: Vec<&str> = matches
+        .values_of("kid")
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    // Secrets after the last algorithm given are used with it too
+    let algorithm = |index: usize| algorithms[index.min(algorithms.len() - 1)];
+    let mut options = EncodeOptions::new(secrets[0])
+        .algorithm(algorithm(0))
+        .serialization(Serialization::from_string(
+            matches.value_of("serialization").unwrap(),
+        ));
 
-fn encoding_key_from_secret(alg: &Algorithm, secret_string: &str, formatopt: Option<&str>) -> JWTResult<EncodingKey> {
-    let secret = 
-        if secret_string.starts_with('@') {
-            slurp_file(&secret_string.chars().skip(1).collect::<String>())
-        } else {
-            secret_string.as_bytes().to_vec()
-        };        
-    
-    let format = 
-        match formatopt {
-            None => {
-                if secret_string.starts_with('@'){
-                    match Path::new(secret_string).extension().and_then(OsStr::to_str) {
-                        Some("pem") | Some("cer") | Some("key") => KeyFormat::PEM,
-                        Some("der") => KeyFormat::DER,
-                        Some("jwk") => KeyFormat::JWK,
-                        _ => KeyFormat::PEM
-                    }
-                } else {
-                    KeyFormat::PEM
-                }
-            }
-            Some("pem") => KeyFormat::PEM,
-            Some("der") => KeyFormat::DER,
-            Some("jwk") => KeyFormat::JWK,
-            Some(_) => KeyFormat::PEM
+    if let Some(kid) = kids.first() {
+        options = options.kid(kid);
+    }
+    for (index, secret) in secrets.iter().enumerate().skip(1) {
+        options = options.add_signer(secret, algorithm(index), kids.get(index).copied());
+    }
+    if let Some(format) = matches.value_of("keyformat") {
+        options = options.key_format(KeyFormat::from_string(format));
+    }
+    if let Some(payloads) = matches.values_of("payload") {
+        for payload in payloads {
+            options = options.claim(PayloadItem::split_payload_item(payload)?);
+        }
+    }
+    if let Some(json) = matches.value_of("json") {
+        let json = match json.strip_prefix('@') {
+            Some(path) => jwt_cli::read_payload_file(path)?,
+            None => read_stdin_if_dash(json)?,
         };
 
-    match alg {
-        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
-            Ok(EncodingKey::from_secret(&secret))            
-        }
-        Algorithm::RS256
-        | Algorithm::RS384
-        | Algorithm::RS512
-        | Algorithm::PS256
-        | Algorithm::PS384
-        | Algorithm::PS512 => {            
-            match format {
-                KeyFormat::PEM => EncodingKey::from_rsa_pem(&secret),
-                KeyFormat::DER => Ok(EncodingKey::from_rsa_der(&secret)),
-                KeyFormat::JWK => {
-                    let jwk: JsonWebKey = str::from_utf8(&secret).unwrap().parse().unwrap();
-                    EncodingKey::from_rsa_pem(&jwk.key.to_pem().as_bytes())
-                }
-            }
-        }
-        Algorithm::ES256 | Algorithm::ES384 => {        
-            match format {
-                KeyFormat::PEM => EncodingKey::from_ec_pem(&secret),
-                KeyFormat::DER => Ok(EncodingKey::from_ec_der(&secret)),
-                KeyFormat::JWK => {
-                    let jwk: JsonWebKey = str::from_utf8(&secret).unwrap().parse().unwrap();
-                    EncodingKey::from_ec_pem(&jwk.key.to_pem().as_bytes())
-                }
-            }
+        options = options.json(&json);
+    }
+    if let Some(path) = matches.value_of("template") {
+        let template = fs::read_to_string(path)
+            .map_err(|err| Error::Io(format!("unable to read {}: {}", path, err)))?;
+        let vars: BTreeMap<String, String> = matches
+            .values_of("var")
+            .map(|vars| {
+                vars.filter_map(|var| var.split_once('='))
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        options = options.json(&jwt_cli::render_template(
+            &template,
+            &vars,
+            Utc::now().timestamp(),
+        )?);
+    }
+    if let Some(path) = matches.value_of("cert_chain") {
+        options = options.cert_chain(path);
+    }
+    if let Some(typ) = matches.value_of("type") {
+        options = options.typ(typ);
+    }
+    if let Some(cty) = matches.value_of("content_type") {
+        options = options.cty(cty);
+    }
+    if let Some(params) = matches.values_of("header") {
+        for param in params {
+            options = options.header_param(PayloadItem::split_payload_item(param)?);
         }
     }
+    if let Some(json) = matches.value_of("header_json") {
+        options = options.header_json(json);
+    }
+    if matches.is_present("detached") {
+        options = options.detached();
+    }
+    if let Some(path) = matches.value_of("payload_file") {
+        let payload =
+            fs::read(path).map_err(|err| Error::Io(format!("unable to read {}: {}", path, err)))?;
+
+        options = options.payload(&payload);
+    }
+    if matches.occurrences_of("expires") > 0 {
+        options = options.expires(matches.value_of("expires").unwrap());
+    }
+    if let Some(not_before) = matches.value_of("not_before") {
+        options = options.not_before(not_before);
+    }
+    if matches.is_present("no_iat") {
+        options = options.no_iat();
+    }
+    if let Some(issuer) = matches.value_of("issuer") {
+        options = options.issuer(issuer);
+    }
+    if let Some(subject) = matches.value_of("subject") {
+        options = options.subject(subject);
+    }
+    if let Some(audience) = matches.value_of("audience") {
+        options = options.audience(audience);
+    }
+    if let Some(jwt_id) = matches.value_of("jwt_id") {
+        options = options.jwt_id(jwt_id);
+    }
+    if let Some(key) = matches.value_of("encrypt") {
+        options = options
+            .encrypt(key)
+            .content_encryption(ContentEncryptionAlgorithm::from_string(
+                matches.value_of("enc").unwrap(),
+            )?);
+    }
+    if let Some(format) = matches.value_of("encrypt_keyformat") {
+        options = options.encryption_key_format(KeyFormat::from_string(format));
+    }
+    if let Some(alg) = matches.value_of("encrypt_alg") {
+        options = options.encryption_algorithm(KeyManagementAlgorithm::from_string(alg)?);
+    }
+
+    jwt_cli::encode_token(&options)
 }
 
-fn decoding_key_from_secret(
-    alg: &Algorithm,
-    secret_string: &str,
-    formatopt: Option<&str>,
-    kid: Option<&String>
-) -> JWTResult<DecodingKey<'static>> {
-    let secret = 
-        if secret_string.starts_with('@') {
-            slurp_file(&secret_string.chars().skip(1).collect::<String>())
-        } else {
-            secret_string.as_bytes().to_vec()
-        };        
-    
-    let format = 
-        match formatopt {
-            None => {
-                if secret_string.starts_with('@'){
-                    match Path::new(secret_string).extension().and_then(OsStr::to_str) {
-                        Some("pem") | Some("cer") | Some("key") => KeyFormat::PEM,
-                        Some("der") => KeyFormat::DER,
-                        Some("jwk") => KeyFormat::JWK,
-                        _ => KeyFormat::PEM
-                    }
-                } else {
-                    KeyFormat::PEM
-                }
-            }
-            Some("pem") => KeyFormat::PEM,
-            Some("der") => KeyFormat::DER,
-            Some("jwk") => KeyFormat::JWK,
-            Some(_) => KeyFormat::PEM
-        };
-    
-    let selected_key = match (&format, kid) {
-        (KeyFormat::JWK, Some(kid)) => {
-            let obj: Value = serde_json::from_str(str::from_utf8(&secret).unwrap())?;            
-            match &obj["keys"] {                
-                Value::Array(ar) => {
-                    match ar.iter().find(|x| match &x["kid"] {
-                        Value::String(s) => kid.eq(s),
-                        _ => false
-                    }) {
-                        Some(kobj) => {                            
-                            Some(serde_json::to_string(&kobj)?)
-                        },
-                        _ => return Err(Error::from(ErrorKind::InvalidSignature))
-                    }
-                }                        
-                _ => Some(String::from_utf8(secret.clone())?),
-            }
+fn decode_token(matches: &ArgMatches) -> (DecodeOutcome, OutputFormat, String) {
+    let jwt = read_stdin_if_dash(matches.value_of("jwt").unwrap()).unwrap_or_else(|err| {
+        bunt::eprintln!("{$red+bold}The JWT couldn't be read{/$}\n");
+        eprintln!("{}", err);
+        exit(err.exit_code())
+    });
+    let options = DecodeOptions {
+        algorithm: matches
+            .value_of("algorithm")
+            .map(|alg| SupportedAlgorithms::from_string(alg).unwrap()),
+        secret: matches.value_of("secret").map(String::from),
+        key_format: matches.value_of("keyformat").map(KeyFormat::from_string),
+        inner_secret: matches.value_of("inner_secret").map(String::from),
+        inner_key_format: matches
+            .value_of("inner_keyformat")
+            .map(KeyFormat::from_string),
+        jwks_source: match (
+            matches.value_of("jwks_url"),
+            matches.value_of("issuer_discovery"),
+        ) {
+            (Some(url), _) => Some(JwksSource::Url(url.to_string())),
+            (_, Some(issuer)) => Some(JwksSource::Issuer(issuer.to_string())),
+            _ => None,
         },
-        _ => None
+        jwks_cache_dir: matches
+            .value_of("jwks_cache_dir")
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("jwt-cli"))),
+        ignore_exp: matches.is_present("ignore_exp"),
+        leeway: matches.value_of("leeway").map(parse_seconds),
+        expected_issuer: matches.value_of("expect_iss").map(String::from),
+        expected_audiences: matches
+            .values_of("expect_aud")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default(),
+        expected_subject: matches.value_of("expect_sub").map(String::from),
+        required_claims: matches
+            .values_of("require_claim")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default(),
+        validate_nbf: matches.is_present("validate_nbf"),
+        max_age: matches.value_of("max_age").map(parse_seconds),
+        policy: matches.value_of("policy").map(read_policy),
+        detached_payload: matches
+            .value_of("detached_payload")
+            .map(read_detached_payload),
+        trust_anchors: matches
+            .value_of("trust_anchor")
+            .map(read_trust_anchors)
+            .unwrap_or_default(),
+        iso_dates: matches.is_present("iso_dates"),
+        date_claims: date_claims(matches),
     };
 
-    
-    match alg {
-        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
-            Ok(DecodingKey::from_secret(&secret).into_static())            
-        }
-        Algorithm::RS256
-        | Algorithm::RS384
-        | Algorithm::RS512
-        | Algorithm::PS256
-        | Algorithm::PS384
-        | Algorithm::PS512 => {
-            match format {
-                KeyFormat::PEM => DecodingKey::from_rsa_pem(&secret).map(DecodingKey::into_static),
-                KeyFormat::DER => Ok(DecodingKey::from_rsa_der(&secret).into_static()),
-                KeyFormat::JWK => {
-                    let jwk: JsonWebKey = selected_key.unwrap().parse().unwrap();
-                    DecodingKey::from_rsa_pem(&jwk.key.to_pem().as_bytes()).map(DecodingKey::into_static)
-                }
-            }            
-        }
-        Algorithm::ES256 | Algorithm::ES384 => {
-            match format {
-                KeyFormat::PEM => DecodingKey::from_ec_pem(&secret).map(DecodingKey::into_static),
-                KeyFormat::DER => Ok(DecodingKey::from_ec_der(&secret).into_static()),
-                KeyFormat::JWK => {
-                    let jwk: JsonWebKey = selected_key.unwrap().parse().unwrap();
-                    DecodingKey::from_ec_pem(&jwk.key.to_pem().as_bytes()).map(DecodingKey::into_static)                    
-                }
-            }
-        }
-    }
+    let ofmt = if matches.is_present("json") {
+        OutputFormat::Json
+    } else if let Some(query) = matches.value_of("query") {
+        OutputFormat::Query(query.to_string())
+    } else {
+        OutputFormat::from_string(matches.value_of("output").unwrap_or("text"))
+    };
+
+    (jwt_cli::decode_token(&jwt, &options), ofmt, jwt)
 }
 
-fn encode_token(matches: &ArgMatches) -> JWTResult<String> {
-    let algorithm = translate_algorithm(SupportedAlgorithms::from_string(
-        matches.value_of("algorithm").unwrap(),
-    ));
-    let kid = matches.value_of("kid");
-    let header = create_header(algorithm, kid);
-    let custom_payloads: Option<Vec<Option<PayloadItem>>> =
-        matches.values_of("payload").map(|maybe_payloads| {
-            maybe_payloads
-                .map(|p| PayloadItem::from_string(Some(p)))
-                .collect()
-        });
-    let custom_payload = matches
-        .value_of("json")
-        .map(|value| {
-            if value != "-" {
-                return String::from(value);
-            }
+fn date_claims(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .values_of("date_claim")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default()
+}
 
-            let mut buffer = String::new();
+fn read_policy(path: &str) -> Policy {
+    Policy::read(Path::new(path)).unwrap_or_else(|err| {
+        bunt::eprintln!("{$red+bold}The policy couldn't be read{/$}\n");
+        eprintln!("{}", err);
+        exit(err.exit_code())
+    })
+}
 
-            io::stdin()
-                .read_line(&mut buffer)
-                .expect("STDIN was not valid UTF-8");
+fn read_detached_payload(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| {
+        bunt::eprintln!("{$red+bold}The detached payload couldn't be read{/$}\n");
+        eprintln!("{}", err);
+        exit(Error::Io(err.to_string()).exit_code())
+    })
+}
 
-            buffer
-        })
-        .map(|raw_json| match from_str(&raw_json) {
-            Ok(Value::Object(json_value)) => json_value
-                .into_iter()
-                .map(|(json_key, json_val)| Some(PayloadItem(json_key, json_val)))
-                .collect(),
-            _ => panic!("Invalid JSON provided!"),
-        });
-    let now = Utc::now().timestamp();
-    let expires = match matches.occurrences_of("expires") {
-        0 => None,
-        _ => PayloadItem::from_timestamp_with_name(matches.value_of("expires"), "exp", now),
-    };
-    let not_before =
-        PayloadItem::from_timestamp_with_name(matches.value_of("not_before"), "nbf", now);
-    let issued_at = match matches.is_present("no_iat") {
-        true => None,
-        false => PayloadItem::from_timestamp_with_name(Some(&now.to_string()), "iat", now),
+fn read_trust_anchors(path: &str) -> Vec<Vec<u8>> {
+    jwt_cli::read_cert_chain(path).unwrap_or_else(|err| {
+        bunt::eprintln!("{$red+bold}The trust anchors couldn't be read{/$}\n");
+        eprintln!("{}", err);
+        exit(err.exit_code())
+    })
+}
+
+fn generate_key(matches: &ArgMatches) -> Result<GeneratedKey, Error> {
+    let algorithm = SupportedAlgorithms::from_string(matches.value_of("algorithm").unwrap())?;
+    let mut options = KeygenOptions::new(algorithm)
+        .key_format(KeyFormat::from_string(
+            matches.value_of("keyformat").unwrap(),
+        ))
+        .rsa_bits(matches.value_of("bits").unwrap().parse().unwrap());
+
+    if let Some(kid) = matches.value_of("kid") {
+        options = options.kid(kid);
+    }
+
+    jwt_cli::generate_key(&options)
+}
+
+// Writes a key to a file, or prints it without one. The file of a private key can only be
+// read by its owner.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_key(key: &[u8], file_name: Option<&str>, private: bool) -> Result<(), Error> {
+    let file_name = match file_name {
+        Some(file_name) => file_name,
+        None => {
+            println!("{}", String::from_utf8_lossy(key).trim_end());
+            return Ok(());
+        }
     };
-    let issuer = PayloadItem::from_string_with_name(matches.value_of("issuer"), "iss");
-    let subject = PayloadItem::from_string_with_name(matches.value_of("subject"), "sub");
-    let audience = PayloadItem::from_string_with_name(matches.value_of("audience"), "aud");
-    let jwt_id = PayloadItem::from_string_with_name(matches.value_of("jwt_id"), "jti");
-    let mut maybe_payloads: Vec<Option<PayloadItem>> = vec![
-        issued_at, expires, issuer, subject, audience, jwt_id, not_before,
-    ];
-
-    maybe_payloads.append(&mut custom_payloads.unwrap_or_default());
-    maybe_payloads.append(&mut custom_payload.unwrap_or_default());
-
-    let payloads = maybe_payloads.into_iter().flatten().collect();
-    let Payload(claims) = Payload::from_payloads(payloads);
-
-    encoding_key_from_secret(&algorithm, matches.value_of("secret").unwrap(), matches.value_of("keyformat"))
-        .and_then(|secret| encode(&header, &claims, &secret))
-}
-
-fn decode_token(
-    matches: &ArgMatches,
-) -> (
-    JWTResult<TokenData<Payload>>,
-    JWTResult<TokenData<Payload>>,
-    OutputFormat,
-) {
-    let algorithm = translate_algorithm(SupportedAlgorithms::from_string(
-        matches.value_of("algorithm").unwrap(),
-    ));
-    
-    let jwt = matches
-        .value_of("jwt")
-        .map(|value| {
-            if value != "-" {
-                return String::from(value);
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    if private {
+        options.mode(0o600);
+    }
+
+    options
+        .open(file_name)
+        .and_then(|mut file| {
+            // An existing file keeps its permissions otherwise
+            #[cfg(unix)]
+            if private {
+                file.set_permissions(fs::Permissions::from_mode(0o600))?;
             }
 
-            let mut buffer = String::new();
+            file.write_all(key)
+        })
+        .map_err(|err| Error::Io(format!("unable to write {}: {}", file_name, err)))
+}
 
-            io::stdin()
-                .read_line(&mut buffer)
-                .expect("STDIN was not valid UTF-8");
+fn print_generated_key(key: Result<GeneratedKey, Error>, matches: &ArgMatches) {
+    let written = key.and_then(|key| {
+        write_key(&key.private_key, matches.value_of("out"), true).and_then(|_| {
+            match &key.public_key {
+                Some(public_key) => {
+                    if matches.value_of("out").is_none() && matches.value_of("public_out").is_none()
+                    {
+                        println!();
+                    }
 
-            buffer
+                    write_key(public_key, matches.value_of("public_out"), false)
+                }
+                None => Ok(()),
+            }
         })
-        .unwrap()
-        .trim()
-        .to_owned();
-
-    let secret_validator = Validation {
-        leeway: 1000,
-        algorithms: vec![algorithm],
-        validate_exp: !matches.is_present("ignore_exp"),
-        ..Default::default()
+    });
+
+    match written {
+        Ok(_) => exit(0),
+        Err(err) => {
+            bunt::eprintln!("{$red+bold}Something went awry generating the key{/$}\n");
+            eprintln!("{}", err);
+            exit(err.exit_code());
+        }
+    }
+}
+
+// Runs one of the `jwks` operations, returning what to print
+fn manage_jwks(matches: &ArgMatches) -> Result<String, Error> {
+    let (operation, matches) = match matches.subcommand() {
+        (operation, Some(matches)) => (operation, matches),
+        _ => unreachable!("clap requires a jwks subcommand"),
     };
+    let path = Path::new(matches.value_of("jwks").unwrap());
+    let mut set = JwkSet::read(path)?;
+
+    match operation {
+        "add" => {
+            let mut jwk = jwt_cli::jwk_from_key(
+                matches.value_of("key").unwrap(),
+                matches.value_of("keyformat").map(KeyFormat::from_string),
+            )?;
 
-    let token_data = dangerous_insecure_decode::<Payload>(&jwt).map(|mut token| {
-        if matches.is_present("iso_dates") {
-            token.claims.convert_timestamps();
+            for (member, arg) in &[("kid", "kid"), ("alg", "algorithm"), ("use", "use")] {
+                if let (Some(value), Value::Object(members)) = (matches.value_of(arg), &mut jwk) {
+                    members.insert(member.to_string(), Value::String(value.to_string()));
+                }
+            }
+
+            let kid = set.add(jwk)?;
+
+            set.write(path)?;
+
+            Ok(format!("Added the key {} to {}", kid, path.display()))
         }
+        "remove" => {
+            let kid = matches.value_of("kid").unwrap();
 
-        token
-    });
+            if !set.remove(kid) {
+                return Err(Error::KidNotFound(kid.to_string()));
+            }
 
-    let kid = match &token_data{
-        Ok(token) => match &token.header.kid{
-            Some(kid) => Some(kid),
-            _ => None
-        },
-        _ => None
-    };
+            set.write(path)?;
 
-    let ofmt = if matches.is_present("json") {
-        OutputFormat::Json
-    } else {
-        OutputFormat::Text
-    };
+            Ok(format!("Removed the key {} from {}", kid, path.display()))
+        }
+        "list" => {
+            let member = |key: &Value, name: &str| key[name].as_str().unwrap_or("-").to_string();
+            let rows: Vec<Vec<String>> = set
+                .keys
+                .iter()
+                .map(|key| {
+                    ["kid", "kty", "alg", "use"]
+                        .iter()
+                        .map(|name| member(key, name))
+                        .collect()
+                })
+                .collect();
+            let kid_width = rows
+                .iter()
+                .map(|row| row[0].len())
+                .max()
+                .unwrap_or(0)
+                .max(3);
 
-    let secret = match matches.value_of("secret").map(|s| (s, !s.is_empty())) {
-        Some((secret, true)) => match decoding_key_from_secret(&algorithm, secret, matches.value_of("keyformat"), kid) {
-            Ok(val) => Some(val),
-            Err(kind) => return (Err(kind), token_data, ofmt)
-        },
-        _ => None,
-    };
+            Ok(std::iter::once(vec![
+                "kid".to_string(),
+                "kty".to_string(),
+                "alg".to_string(),
+                "use".to_string(),
+            ])
+            .chain(rows)
+            .map(|row| {
+                format!(
+                    "{:kid_width$}  {:3}  {:6}  {}",
+                    row[0],
+                    row[1],
+                    row[2],
+                    row[3],
+                    kid_width = kid_width
+                )
+                .trim_end()
+                .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n"))
+        }
+        "public" => {
+            let public_set = set.public();
 
-    (
-        match secret {
-            Some(secret_key) => decode::<Payload>(&jwt, &secret_key, &secret_validator),
-            None => dangerous_insecure_decode::<Payload>(&jwt),
-        },
-        token_data,
-        ofmt,
-    )
+            match matches.value_of("out") {
+                Some(out) => {
+                    public_set.write(Path::new(out))?;
+                    Ok(format!("Wrote the public keys to {}", out))
+                }
+                None => Ok(to_string_pretty(&public_set)?),
+            }
+        }
+        _ => unreachable!("clap only allows the known jwks subcommands"),
+    }
+}
+
+fn print_jwks_result(result: Result<String, Error>) {
+    match result {
+        Ok(output) => {
+            println!("{}", output);
+            exit(0);
+        }
+        Err(err) => {
+            bunt::eprintln!("{$red+bold}Something went awry with the JWK Set{/$}\n");
+            eprintln!("{}", err);
+            exit(err.exit_code());
+        }
+    }
 }
 
-fn print_encoded_token(token: JWTResult<String>) {
+fn print_encoded_token(token: Result<String, Error>) {
     match token {
         Ok(jwt) => {
             if atty::is(Stream::Stdout) {
@@ -664,73 +993,403 @@ fn print_encoded_token(token: JWTResult<String>) {
         Err(err) => {
             bunt::eprintln!("{$red+bold}Something went awry creating the jwt{/$}\n");
             eprintln!("{}", err);
+            exit(err.exit_code());
+        }
+    }
+}
+
+// Removes the nulls TOML has no way to write from objects and arrays
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(members) => Value::Object(
+            members
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, value)| (name, without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .filter(|value| !value.is_null())
+                .map(without_nulls)
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+// The token and how it was verified as a single document, which only has the verification
+// when the token can't be decoded, with the code to exit with
+fn decoded_document(outcome: DecodeOutcome) -> (Value, i32) {
+    let verification = outcome.verification();
+    let exit_code = match &outcome.validated {
+        Err(err) => err.exit_code(),
+        Ok(_) => 0,
+    };
+    match outcome.token_data {
+        Ok(token) => {
+            let timing = Timing::new(&token.claims, Utc::now().timestamp());
+            let output = TokenOutput {
+                layers: outcome.layers,
+                signatures: outcome.signatures,
+                cert_chain: outcome.cert_chain,
+                verification: Some(verification),
+                timing: Some(timing),
+                ..TokenOutput::new(token)
+            };
+
+            (serde_json::to_value(output).unwrap(), exit_code)
+        }
+        Err(_) => (
+            serde_json::json!({ "verification": verification }),
+            exit_code.max(1),
+        ),
+    }
+}
+
+// Prints the decoded document as JSON, YAML or TOML
+fn print_decoded_document(outcome: DecodeOutcome, format: OutputFormat) {
+    let (output, exit_code) = decoded_document(outcome);
+    let rendered = match format {
+        OutputFormat::Yaml => serde_yaml::to_string(&output).map_err(|err| err.to_string()),
+        OutputFormat::Toml => {
+            toml::to_string_pretty(&without_nulls(output)).map_err(|err| err.to_string())
+        }
+        _ => to_string_pretty(&output)
+            .map(|json| json + "\n")
+            .map_err(|err| err.to_string()),
+    };
+
+    match rendered {
+        Ok(rendered) => print!("{}", rendered),
+        Err(err) => {
+            bunt::eprintln!("{$red+bold}The decoded JWT couldn't be rendered{/$}\n");
+            eprintln!("{}", err);
             exit(1);
         }
     }
+
+    exit(exit_code)
+}
+
+// Prints the value `query` leads to in the decoded document, a string as it is and anything
+// else as compact JSON, exiting with 6 when there's nothing there
+fn print_query_result(outcome: DecodeOutcome, query: &str) {
+    let (output, exit_code) = decoded_document(outcome);
+
+    match jwt_cli::query(&output, query) {
+        Ok(Some(Value::String(string))) => println!("{}", string),
+        Ok(Some(value)) => println!("{}", value),
+        Ok(None) if exit_code != 0 => (),
+        Ok(None) => {
+            eprintln!("{} isn't in the decoded token", query);
+            exit(6);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(6);
+        }
+    }
+
+    exit(exit_code)
+}
+
+// Prints each base64url segment of a compact token with what it decodes to, as text when
+// it's UTF-8, like the header and claims, and otherwise as hex, like a signature
+fn print_raw_segments(jwt: &str) {
+    let compact_tokens = match jwt_cli::is_json_serialization(jwt) {
+        true => jwt_cli::split_json_serialization(jwt)
+            .map(|signatures| signatures.into_iter().map(|sig| sig.compact).collect())
+            .unwrap_or_default(),
+        false => vec![jwt.to_string()],
+    };
+
+    for token in compact_tokens {
+        let names = match token.split('.').count() {
+            5 => vec!["header", "encrypted key", "iv", "ciphertext", "tag"],
+            _ => vec!["header", "payload", "signature"],
+        };
+
+        for (name, segment) in names.iter().zip(token.split('.')) {
+            let decoded = match URL_SAFE_NO_PAD.decode(segment) {
+                Ok(bytes) => String::from_utf8(bytes).unwrap_or_else(|err| {
+                    err.as_bytes()
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect()
+                }),
+                Err(_) => "(not base64url)".to_string(),
+            };
+
+            bunt::println!("{$bold}{}{/$}", name);
+            println!("{}\n{}\n", segment, decoded);
+        }
+    }
+}
+
+// Prints the claims as a table, explaining the registered ones
+fn print_claims_table(claims: &Payload) {
+    let rows: Vec<Vec<String>> = claims
+        .0
+        .iter()
+        .map(|(name, value)| {
+            vec![
+                name.clone(),
+                match value {
+                    Value::String(string) => string.clone(),
+                    value => value.to_string(),
+                },
+                jwt_cli::claim_description(name)
+                    .unwrap_or_default()
+                    .to_string(),
+            ]
+        })
+        .collect();
+    let width = |column: usize, title: &str| {
+        rows.iter()
+            .map(|row| row[column].chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(title.len())
+    };
+    let (claim_width, value_width) = (width(0, "claim"), width(1, "value"));
+
+    for row in std::iter::once(vec![
+        "claim".to_string(),
+        "value".to_string(),
+        "description".to_string(),
+    ])
+    .chain(rows.iter().cloned())
+    {
+        println!(
+            "{}",
+            format!(
+                "{:claim_width$}  {:value_width$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                claim_width = claim_width,
+                value_width = value_width
+            )
+            .trim_end()
+        );
+    }
+}
+
+// Prints the claims as JSON, with a comment after each date claim saying when it is
+fn print_claims(claims: &Payload, date_claims: &[String]) {
+    let now = Utc::now().timestamp();
+    let notes: Vec<(String, String)> = claims
+        .dates(date_claims)
+        .into_iter()
+        .filter_map(|(name, timestamp)| {
+            let note = jwt_cli::describe_date(&name, timestamp, now)?;
+
+            Some((format!("  {}: ", Value::from(name)), note))
+        })
+        .collect();
+
+    for line in to_string_pretty(claims).unwrap().lines() {
+        match notes.iter().find(|(start, _)| line.starts_with(start)) {
+            Some((_, note)) => bunt::println!("{} {$dimmed}// {}{/$}", line, note),
+            None => println!("{}", line),
+        }
+    }
 }
 
 fn print_decoded_token(
-    validated_token: JWTResult<TokenData<Payload>>,
-    token_data: JWTResult<TokenData<Payload>>,
+    outcome: DecodeOutcome,
     format: OutputFormat,
+    jwt: &str,
+    date_claims: &[String],
 ) {
-    if let Err(err) = &validated_token {
-        match err.kind() {
-            ErrorKind::InvalidToken => {
-                bunt::println!("{$red+bold}The JWT provided is invalid{/$}")
+    if let OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Toml = format {
+        return print_decoded_document(outcome, format);
+    }
+    if let OutputFormat::Query(query) = &format {
+        return print_query_result(outcome, query);
+    }
+
+    let DecodeOutcome {
+        validated: validated_token,
+        token_data,
+        verified_with,
+        keys_tried,
+        policy_violations,
+        layers,
+        invalid_layer,
+        signatures,
+        cert_chain,
+        ..
+    } = outcome;
+
+    if !policy_violations.is_empty() {
+        bunt::eprintln!(
+            "{$red+bold}The JWT provided breaks {} rule(s) of the policy{/$}",
+            policy_violations.len()
+        );
+
+        for violation in &policy_violations {
+            eprintln!("  - {}", violation);
+        }
+    } else if let Err(err) = &validated_token {
+        match err {
+            Error::InvalidSignature if keys_tried == 0 => {
+                bunt::eprintln!("{$red+bold}The secret provided has no key which can verify the JWT{/$}")
             }
-            ErrorKind::InvalidSignature => {
+            Error::InvalidSignature if keys_tried > 1 => bunt::eprintln!(
+                "{$red+bold}The JWT provided has an invalid signature for all {} keys which could verify it{/$}",
+                keys_tried
+            ),
+            Error::InvalidSignature => {
                 bunt::eprintln!("{$red+bold}The JWT provided has an invalid signature{/$}")
             }
-            ErrorKind::InvalidRsaKey => {
-                bunt::eprintln!("{$red+bold}The secret provided isn't a valid RSA key{/$}")
+            Error::Validation(ValidationError::Expired) => {
+                bunt::eprintln!("{$red+bold}The token has expired. An expired `exp` claim can be ignored via the `--ignore-exp` parameter.{/$}")
             }
-            ErrorKind::InvalidEcdsaKey => {
-                bunt::eprintln!("{$red+bold}The secret provided isn't a valid ECDSA key{/$}")
+            Error::Validation(ValidationError::TooOld(_)) => bunt::eprintln!(
+                "{$red+bold}The token was issued longer ago than `--max-age` allows:{/$} {}",
+                err
+            ),
+            Error::Validation(ValidationError::MissingClaim(claim)) if claim == "exp" => {
+                bunt::eprintln!("{$red+bold}The `exp` claim is not set. This error can be ignored via the `--ignore-exp` parameter.{/$}")
             }
-            ErrorKind::ExpiredSignature => {
-                bunt::eprintln!("{$red+bold}The token has expired (or the `exp` claim is not set). This error can be ignored via the `--ignore-exp` parameter.{/$}")
+            Error::Validation(ValidationError::MissingClaim(claim)) => {
+                bunt::eprintln!(
+                    "{$red+bold}The `{}` claim is required but isn't set{/$}",
+                    claim
+                )
             }
-            ErrorKind::InvalidIssuer => {
-                bunt::println!("{$red+bold}The token issuer is invalid{/$}")
+            Error::Validation(ValidationError::Issuer) => {
+                bunt::eprintln!("{$red+bold}The token issuer isn't the expected one{/$}")
             }
-            ErrorKind::InvalidAudience => {
-                bunt::eprintln!("{$red+bold}The token audience doesn't match the subject{/$}")
+            Error::Validation(ValidationError::Audience) => {
+                bunt::eprintln!("{$red+bold}The token audience isn't one of the expected ones{/$}")
             }
-            ErrorKind::InvalidSubject => {
-                bunt::eprintln!("{$red+bold}The token subject doesn't match the audience{/$}")
+            Error::Validation(ValidationError::Subject) => {
+                bunt::eprintln!("{$red+bold}The token subject isn't the expected one{/$}")
             }
-            ErrorKind::ImmatureSignature => bunt::eprintln!(
+            Error::Validation(ValidationError::NotYetValid) => bunt::eprintln!(
                 "{$red+bold}The `nbf` claim is in the future which isn't allowed{/$}"
             ),
-            ErrorKind::InvalidAlgorithm => bunt::eprintln!(
+            Error::Validation(ValidationError::Algorithm) => bunt::eprintln!(
                 "{$red+bold}The JWT provided has a different signing algorithm than the one you \
-                     provided{/$}",
+                     provided, or one the secret isn't meant for{/$}",
             ),
-            _ => bunt::eprintln!(
-                "{$red+bold}The JWT provided is invalid because{/$} {:?}",
+            Error::Io(_) => bunt::eprintln!(
+                "{$red+bold}The keys to validate the JWT with couldn't be loaded:{/$} {}",
+                err
+            ),
+            Error::KeyParse { .. } => bunt::eprintln!(
+                "{$red+bold}The secret provided isn't a valid key:{/$} {}",
+                err
+            ),
+            Error::KidNotFound(kid) => bunt::eprintln!(
+                "{$red+bold}The secret provided has no key with the kid {} of the JWT{/$}",
+                kid
+            ),
+            Error::UnsupportedAlgorithm(_) => bunt::eprintln!(
+                "{$red+bold}The JWT provided has a different signing algorithm than the one you \
+                     provided, or one the secret isn't meant for:{/$} {}",
+                err
+            ),
+            Error::Policy(violations) => {
+                bunt::eprintln!("{$red+bold}The options given don't go with the policy{/$}");
+
+                for violation in violations {
+                    eprintln!("  - {}", violation);
+                }
+            }
+            Error::Malformed(_) | Error::Crypto(_) => bunt::eprintln!(
+                "{$red+bold}The JWT provided is invalid because{/$} {}",
                 err
             ),
         };
     }
 
+    if let (Err(_), Some(layer)) = (&validated_token, invalid_layer) {
+        if layers.len() > 1 {
+            eprintln!(
+                "The error is in layer {} of {}, counting from the outermost",
+                layer + 1,
+                layers.len()
+            );
+        }
+    }
+
+    for (index, signature) in signatures.iter().enumerate() {
+        let result = match (&signature.verified_with, &signature.error) {
+            (Some(kid), _) => format!("verified with the key {}", kid),
+            (None, _) if signature.verified => "verified".to_string(),
+            (None, Some(error)) => format!("invalid because {}", error),
+            (None, None) => "not verified".to_string(),
+        };
+
+        eprintln!(
+            "Signature {} of {} ({}): {}",
+            index + 1,
+            signatures.len(),
+            signature.header.alg,
+            result
+        );
+    }
+
+    if let (true, Ok(_), Some(kid)) = (signatures.is_empty(), &validated_token, verified_with) {
+        // On stderr, so that the token can still be piped
+        eprintln!("Verified with the key {}", kid);
+    }
+
+    if validated_token.is_ok() {
+        for (index, cert) in cert_chain.iter().enumerate() {
+            eprintln!(
+                "Certificate {} of {}: {} (issued by {}, valid until {})",
+                index + 1,
+                cert_chain.len(),
+                cert.subject,
+                cert.issuer,
+                cert.not_after
+            );
+        }
+    }
+
+    let exit_code = match &validated_token {
+        Err(err) => err.exit_code(),
+        Ok(_) => 0,
+    };
+
     match (format, token_data) {
-        (OutputFormat::Json, Ok(token)) => {
-            println!("{}", to_string_pretty(&TokenOutput::new(token)).unwrap())
+        (OutputFormat::Raw, _) => print_raw_segments(jwt.trim()),
+        (_, Err(_)) => exit(exit_code.max(1)),
+        (OutputFormat::Header, Ok(token)) => {
+            println!("{}", serde_json::to_string(&token.header).unwrap())
+        }
+        (OutputFormat::Payload, Ok(token)) => {
+            println!("{}", serde_json::to_string(&token.claims).unwrap())
+        }
+        (OutputFormat::Table, Ok(token)) => print_claims_table(&token.claims),
+        (_, Ok(token)) if !layers.is_empty() => {
+            for (index, layer) in layers.iter().enumerate() {
+                bunt::println!(
+                    "\n{$bold}Layer {} of {} header\n-------------------{/$}",
+                    index + 1,
+                    layers.len()
+                );
+                println!("{}", to_string_pretty(layer).unwrap());
+            }
+
+            bunt::println!("\n{$bold}Token claims\n------------{/$}");
+            print_claims(&token.claims, date_claims);
         }
         (_, Ok(token)) => {
             bunt::println!("\n{$bold}Token header\n------------{/$}");
             println!("{}\n", to_string_pretty(&token.header).unwrap());
             bunt::println!("{$bold}Token claims\n------------{/$}");
-            println!("{}", to_string_pretty(&token.claims).unwrap());
+            print_claims(&token.claims, date_claims);
         }
-        (_, Err(_)) => exit(1),
     }
 
-    exit(match validated_token {
-        Err(_) => 1,
-        Ok(_) => 0,
-    })
+    exit(exit_code)
 }
 
 fn main() {
@@ -738,16 +1397,24 @@ fn main() {
 
     match matches.subcommand() {
         ("encode", Some(encode_matches)) => {
-            warn_unsupported(encode_matches);
-
             let token = encode_token(encode_matches);
 
             print_encoded_token(token);
         }
         ("decode", Some(decode_matches)) => {
-            let (validated_token, token_data, format) = decode_token(decode_matches);
+            let (outcome, format, jwt) = decode_token(decode_matches);
+
+            print_decoded_token(outcome, format, &jwt, &date_claims(decode_matches));
+        }
+        ("keygen", Some(keygen_matches)) => {
+            let key = generate_key(keygen_matches);
+
+            print_generated_key(key, keygen_matches);
+        }
+        ("jwks", Some(jwks_matches)) => {
+            let result = manage_jwks(jwks_matches);
 
-            print_decoded_token(validated_token, token_data, format);
+            print_jwks_result(result);
         }
         _ => (),
     }
@@ -0,0 +1,367 @@
+use crate::error::Error;
+use chrono::{Local, SecondsFormat, TimeZone, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_str, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+/// A single claim, as a name and a JSON value
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PayloadItem(pub String, pub Value);
+
+/// The full set of claims in a token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Payload(pub BTreeMap<String, Value>);
+
+impl PayloadItem {
+    /// Parses `name=value` like [`PayloadItem::parse`], or gives None when there's
+    /// nothing to parse or it can't be parsed
+    pub fn from_string(val: Option<&str>) -> Option<PayloadItem> {
+        val.and_then(|item| PayloadItem::split_payload_item(item).ok())
+    }
+
+    pub fn from_string_with_name(val: Option<&str>, name: &str) -> Option<PayloadItem> {
+        match val {
+            Some(value) => match from_str(value) {
+                Ok(json_value) => Some(PayloadItem(name.to_string(), json_value)),
+                Err(_) => match from_str(format!("\"{}\"", value).as_str()) {
+                    Ok(json_value) => Some(PayloadItem(name.to_string(), json_value)),
+                    Err(_) => None,
+                },
+            },
+            _ => None,
+        }
+    }
+
+    // If the value is defined as systemd.time, converts the defined duration into a UNIX timestamp
+    pub fn from_timestamp_with_name(
+        val: Option<&str>,
+        name: &str,
+        now: i64,
+    ) -> Option<PayloadItem> {
+        if let Some(timestamp) = val {
+            if timestamp.parse::<u64>().is_err() {
+                let duration = parse_duration::parse(timestamp);
+                if let Ok(parsed_duration) = duration {
+                    let seconds = parsed_duration.as_secs() + now as u64;
+                    return PayloadItem::from_string_with_name(Some(&seconds.to_string()), name);
+                }
+            }
+        }
+
+        PayloadItem::from_string_with_name(val, name)
+    }
+
+    /// Parses `name=value`, whose value is always a string, or `name:=json` for a value of
+    /// any JSON type. Only the first `=` separates the name, so values can have more.
+    pub fn parse(p: &str) -> Result<PayloadItem, String> {
+        let (name, value) = p.split_once('=').ok_or_else(|| {
+            String::from("payloads must have a key and value in the form key=value")
+        })?;
+        let (name, value) = match name.strip_suffix(':') {
+            Some(name) => (
+                name,
+                from_str(value)
+                    .map_err(|err| format!("the value of {} isn't valid JSON: {}", name, err))?,
+            ),
+            None => (name, Value::String(value.to_string())),
+        };
+
+        match name.is_empty() {
+            true => Err(String::from("payloads must have a key")),
+            false => Ok(PayloadItem(name.to_string(), value)),
+        }
+    }
+
+    /// Like [`PayloadItem::parse`], with the reason `p` can't be parsed as an [`Error`]
+    pub fn split_payload_item(p: &str) -> Result<PayloadItem, Error> {
+        PayloadItem::parse(p).map_err(Error::Malformed)
+    }
+}
+
+/// One step along the path to a nested claim
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    /// A member of an object
+    Key(String),
+    /// An element of an array
+    Index(usize),
+    /// A new element at the end of an array
+    Append,
+}
+
+/// Parses the path to a nested claim, such as `user.roles[]` or `matrix[0][1]`. Names with
+/// dots or brackets in them can be quoted, as in `["https://example.com/roles"]`.
+pub fn parse_claim_path(path: &str) -> Result<Vec<PathSegment>, String> {
+    let invalid = || format!("{} isn't a valid claim path", path);
+    let mut segments = vec![];
+    let mut rest = path;
+
+    while !rest.is_empty() {
+        if let Some(bracketed) = rest.strip_prefix('[') {
+            let (segment, after) = match bracketed.strip_prefix('"') {
+                Some(quoted) => {
+                    let (name, after) = quoted.split_once("\"]").ok_or_else(invalid)?;
+
+                    (PathSegment::Key(name.to_string()), after)
+                }
+                None => {
+                    let (index, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+
+                    match index {
+                        "" => (PathSegment::Append, after),
+                        _ => (
+                            PathSegment::Index(index.parse().map_err(|_| invalid())?),
+                            after,
+                        ),
+                    }
+                }
+            };
+
+            segments.push(segment);
+            rest = after;
+        } else {
+            if !segments.is_empty() {
+                rest = rest.strip_prefix('.').ok_or_else(invalid)?;
+            }
+
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+
+            if end == 0 {
+                return Err(invalid());
+            }
+
+            segments.push(PathSegment::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+    }
+
+    match segments.first() {
+        Some(PathSegment::Key(_)) => Ok(segments),
+        _ => Err(invalid()),
+    }
+}
+
+// Sets `value` at `path` inside `target`, creating the objects and arrays on the way
+fn insert_at(target: &mut Value, path: &[PathSegment], value: Value) -> Result<(), ()> {
+    let (segment, rest) = match path.split_first() {
+        Some(step) => step,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+
+    match segment {
+        PathSegment::Key(name) => {
+            if target.is_null() {
+                *target = Value::Object(Default::default());
+            }
+
+            let object = target.as_object_mut().ok_or(())?;
+
+            insert_at(
+                object.entry(name.clone()).or_insert(Value::Null),
+                rest,
+                value,
+            )
+        }
+        PathSegment::Index(_) | PathSegment::Append => {
+            if target.is_null() {
+                *target = Value::Array(vec![]);
+            }
+
+            let array = target.as_array_mut().ok_or(())?;
+            let index = match segment {
+                PathSegment::Index(index) => *index,
+                _ => array.len(),
+            };
+
+            match index.cmp(&array.len()) {
+                Ordering::Greater => return Err(()),
+                Ordering::Equal => array.push(Value::Null),
+                Ordering::Less => (),
+            }
+
+            insert_at(&mut array[index], rest, value)
+        }
+    }
+}
+
+// The registered claims of RFC 7519, and the common ones of OpenID Connect and OAuth
+const REGISTERED_CLAIMS: [(&str, &str); 16] = [
+    ("iss", "Issuer"),
+    ("sub", "Subject"),
+    ("aud", "Audience"),
+    ("exp", "Expiration time"),
+    ("nbf", "Not before"),
+    ("iat", "Issued at"),
+    ("jti", "JWT ID"),
+    ("auth_time", "Time the user authenticated"),
+    ("nonce", "Value tying the token to a client session"),
+    ("azp", "Authorized party the token was issued to"),
+    ("acr", "Authentication context class reference"),
+    ("amr", "Authentication methods references"),
+    ("sid", "Session ID"),
+    ("scope", "Scopes granted to the client"),
+    ("client_id", "Client the token was issued to"),
+    ("cnf", "Confirmation of the key the token is bound to"),
+];
+
+/// What a registered claim, like `exp` or `auth_time`, means
+pub fn claim_description(name: &str) -> Option<&'static str> {
+    REGISTERED_CLAIMS
+        .iter()
+        .find(|(claim, _)| *claim == name)
+        .map(|(_, description)| *description)
+}
+
+// Claims which hold a NumericDate, with the words saying when they happen after and
+// before now
+const DATE_CLAIMS: [(&str, &str, &str); 5] = [
+    ("exp", "expires", "expired"),
+    ("nbf", "valid", "became valid"),
+    ("iat", "issued", "issued"),
+    ("auth_time", "authenticated", "authenticated"),
+    ("updated_at", "updated", "updated"),
+];
+
+fn is_date_claim(name: &str, extra: &[String]) -> bool {
+    DATE_CLAIMS.iter().any(|(claim, _, _)| *claim == name) || extra.iter().any(|c| c == name)
+}
+
+// The seconds since the epoch of a NumericDate, which can have a fraction
+fn numeric_date(value: &Value) -> Option<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_f64().map(|seconds| seconds as i64))
+}
+
+// A number of seconds in the largest unit it has at least one of, like `12m` or `3d`
+fn time_span(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Describes the NumericDate `timestamp` of `claim` as an RFC 3339 date, the same time in
+/// the local timezone and how long before or after `now` it is, like
+/// `2024-05-01T10:00:00Z, 2024-05-01 12:00:00 +02:00 local, expires in 12m`
+pub fn describe_date(claim: &str, timestamp: i64, now: i64) -> Option<String> {
+    let utc = Utc.timestamp_opt(timestamp, 0).single()?;
+    let local = Local.timestamp_opt(timestamp, 0).single()?;
+    let (after, before) = DATE_CLAIMS
+        .iter()
+        .find(|(name, _, _)| *name == claim)
+        .map_or(("", ""), |(_, after, before)| (*after, *before));
+    let span = time_span(timestamp.abs_diff(now));
+    let relative = match timestamp > now {
+        true => format!("{} in {}", after, span),
+        false => format!("{} {} ago", before, span),
+    };
+
+    Some(format!(
+        "{}, {} local, {}",
+        utc.to_rfc3339_opts(SecondsFormat::Secs, true),
+        local.format("%Y-%m-%d %H:%M:%S %:z"),
+        relative.trim_start()
+    ))
+}
+
+impl Payload {
+    pub fn from_payloads(payloads: Vec<PayloadItem>) -> Payload {
+        let mut payload = BTreeMap::new();
+
+        for PayloadItem(k, v) in payloads {
+            payload.insert(k, v);
+        }
+
+        Payload(payload)
+    }
+
+    /// Sets the claim at `path`, as parsed by [`parse_claim_path`], building the nested
+    /// objects and arrays it leads through. Those which exist already are added to.
+    pub fn insert_path(&mut self, path: &str, value: Value) -> Result<(), String> {
+        let segments = parse_claim_path(path)?;
+        let name = match &segments[0] {
+            PathSegment::Key(name) => name.clone(),
+            _ => unreachable!("claim paths start with a name"),
+        };
+        let claim = self.0.entry(name).or_insert(Value::Null);
+
+        insert_at(claim, &segments[1..], value).map_err(|_| {
+            format!(
+                "{} doesn't fit the claims set before it, or skips over array elements",
+                path
+            )
+        })
+    }
+
+    /// The claims holding a NumericDate, the registered ones like `exp` or `auth_time` as
+    /// well as those in `extra`, with their timestamps
+    pub fn dates(&self, extra: &[String]) -> Vec<(String, i64)> {
+        self.0
+            .iter()
+            .filter(|(name, _)| is_date_claim(name, extra))
+            .filter_map(|(name, value)| Some((name.clone(), numeric_date(value)?)))
+            .collect()
+    }
+
+    /// Replaces the NumericDates of the date claims, and those in `extra`, with RFC 3339 dates
+    pub fn convert_timestamps(&mut self, extra: &[String]) {
+        for (name, timestamp) in self.dates(extra) {
+            if let Some(date) = Utc.timestamp_opt(timestamp, 0).single() {
+                self.0.insert(name, date.to_rfc3339().into());
+            }
+        }
+    }
+}
+
+// Converts TOML into JSON, with dates and times as RFC 3339 strings
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => integer.into(),
+        toml::Value::Float(float) => float.into(),
+        toml::Value::Boolean(boolean) => boolean.into(),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(values) => Value::Array(values.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Reads a payload file as JSON. Files with a `.yaml`, `.yml` or `.toml` extension are
+/// converted from YAML or TOML, and anything else is returned as it is.
+pub fn read_payload_file(path: &str) -> Result<String, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::Io(format!("unable to read {}: {}", path, err)))?;
+    let invalid = |format: &str, err: &dyn Display| {
+        Error::Malformed(format!("{} isn't valid {}: {}", path, format, err))
+    };
+    let payload: Value = match Path::new(path).extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(&contents).map_err(|err| invalid("YAML", &err))?
+        }
+        Some("toml") => toml_to_json(
+            contents
+                .parse::<toml::Table>()
+                .map(toml::Value::Table)
+                .map_err(|err| invalid("TOML", &err))?,
+        ),
+        _ => return Ok(contents),
+    };
+
+    Ok(serde_json::to_string(&payload)?)
+}
@@ -0,0 +1,360 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result};
+use crate::keys::KeyFormat;
+use crate::payload::Payload;
+use crate::remote::JwksSource;
+use crate::validation::{numeric_date, Validation};
+use chrono::Utc;
+use regex::Regex;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A validation policy, as read from a TOML file by [`Policy::read`]
+///
+/// ```toml
+/// algorithms = ["RS256", "ES256"]
+/// issuer = "https://issuer.example.com"
+/// audiences = ["api"]
+/// max_lifetime = 3600
+/// leeway = 30
+///
+/// [keys]
+/// jwks_url = "https://issuer.example.com/.well-known/jwks.json"
+///
+/// [claims.email]
+/// type = "string"
+/// pattern = "@example\\.com$"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// The algorithms tokens may be signed with. Any algorithm is allowed when empty.
+    pub algorithms: Vec<SupportedAlgorithms>,
+    /// Where the keys tokens are verified with come from
+    pub keys: KeySources,
+    /// The issuer the `iss` claim must be
+    pub issuer: Option<String>,
+    /// The audiences the `aud` claim has to include at least one of
+    pub audiences: Vec<String>,
+    /// The value the `sub` claim must have
+    pub subject: Option<String>,
+    /// The most seconds there may be between `iat` and `exp`
+    pub max_lifetime: Option<u64>,
+    /// The seconds of clock skew allowed when checking `exp`, `nbf` and `iat`
+    pub leeway: Option<u64>,
+    /// Rules for individual claims, by name
+    pub claims: BTreeMap<String, ClaimRule>,
+}
+
+/// The trusted sources of keys. Only one should be set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeySources {
+    /// A secret or key, which can be prefixed with @ to read it from a file
+    pub secret: Option<String>,
+    /// The format of the secret: pem, der or jwk
+    pub key_format: Option<String>,
+    pub jwks_url: Option<String>,
+    pub issuer_discovery: Option<String>,
+}
+
+/// What a claim has to look like
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClaimRule {
+    /// Whether the claim has to be present. Default: true
+    #[serde(default = "required_by_default")]
+    pub required: bool,
+    #[serde(rename = "type")]
+    pub claim_type: Option<ClaimType>,
+    /// A regular expression string claims have to match
+    pub pattern: Option<String>,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+/// The JSON types a claim can be required to have
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClaimType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl ClaimType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            ClaimType::String => value.is_string(),
+            ClaimType::Number => value.is_number(),
+            ClaimType::Integer => value.is_i64() || value.is_u64(),
+            ClaimType::Boolean => value.is_boolean(),
+            ClaimType::Array => value.is_array(),
+            ClaimType::Object => value.is_object(),
+        }
+    }
+}
+
+impl fmt::Display for ClaimType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ClaimType::String => "a string",
+            ClaimType::Number => "a number",
+            ClaimType::Integer => "an integer",
+            ClaimType::Boolean => "a boolean",
+            ClaimType::Array => "an array",
+            ClaimType::Object => "an object",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A rule of a policy which a token breaks
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyViolation {
+    /// The name of the rule, like `issuer` or `claims.email.pattern`
+    pub rule: String,
+    pub reason: String,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.reason)
+    }
+}
+
+pub(crate) fn violation(rule: &str, reason: String) -> PolicyViolation {
+    PolicyViolation {
+        rule: rule.to_string(),
+        reason,
+    }
+}
+
+impl Policy {
+    /// Reads a policy from a TOML file, checking that its patterns are valid regular expressions
+    pub fn read(path: &Path) -> Result<Policy> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Error::Io(format!("unable to read {}: {}", path.display(), err)))?;
+
+        Policy::from_toml(&contents)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Policy> {
+        let policy: Policy = toml::from_str(contents)
+            .map_err(|err| Error::Malformed(format!("invalid policy: {}", err)))?;
+
+        for (name, rule) in &policy.claims {
+            if let Some(pattern) = &rule.pattern {
+                Regex::new(pattern).map_err(|err| {
+                    Error::Malformed(format!(
+                        "invalid policy: the pattern of the {} claim is invalid: {}",
+                        name, err
+                    ))
+                })?;
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Where the policy says keys come from, as a secret and its format
+    pub fn secret(&self) -> Option<(String, Option<KeyFormat>)> {
+        self.keys.secret.as_ref().map(|secret| {
+            (
+                secret.clone(),
+                self.keys.key_format.as_deref().map(KeyFormat::from_string),
+            )
+        })
+    }
+
+    /// Where the policy says a JWK Set to verify tokens with can be fetched from
+    pub fn jwks_source(&self) -> Option<JwksSource> {
+        match (&self.keys.jwks_url, &self.keys.issuer_discovery) {
+            (Some(url), _) => Some(JwksSource::Url(url.clone())),
+            (_, Some(issuer)) => Some(JwksSource::Issuer(issuer.clone())),
+            _ => None,
+        }
+    }
+
+    /// Sets up `validation` with the rules of the policy it can enforce itself
+    pub fn apply(&self, validation: &mut Validation) {
+        if !self.algorithms.is_empty() {
+            validation.algorithms = self.algorithms.clone();
+        }
+
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        if !self.audiences.is_empty() {
+            validation.set_audience(&self.audiences);
+        }
+
+        if let Some(subject) = &self.subject {
+            validation.set_subject(subject);
+        }
+
+        if let Some(leeway) = self.leeway {
+            validation.leeway = leeway;
+        }
+
+        validation.validate_nbf = true;
+        validation.required_spec_claims.extend(
+            self.claims
+                .iter()
+                .filter(|(_, rule)| rule.required)
+                .map(|(name, _)| name.clone()),
+        );
+    }
+
+    /// Checks the claims of a token signed with `alg`, or only encrypted when there's
+    /// none, against every rule of the policy, returning the rules it breaks. Dates are
+    /// checked with the leeway of `validation`, and `exp` only when it validates it.
+    pub fn check(
+        &self,
+        alg: Option<SupportedAlgorithms>,
+        claims: &Payload,
+        validation: &Validation,
+    ) -> Vec<PolicyViolation> {
+        let mut violations = vec![];
+        let now = Utc::now().timestamp() as f64;
+        let leeway = validation.leeway as f64;
+        let claim = |name: &str| claims.0.get(name);
+        let timestamp = |name: &str| claim(name).and_then(numeric_date);
+
+        if !self.algorithms.is_empty() {
+            match alg {
+                Some(alg) if self.algorithms.contains(&alg) => (),
+                Some(alg) => violations.push(violation(
+                    "algorithms",
+                    format!("the token is signed with {}, which isn't allowed", alg),
+                )),
+                None => violations.push(violation(
+                    "algorithms",
+                    "the token is only encrypted, not signed".to_string(),
+                )),
+            }
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if claim("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+                violations.push(violation(
+                    "issuer",
+                    format!("the `iss` claim isn't {}", issuer),
+                ));
+            }
+        }
+
+        if !self.audiences.is_empty() {
+            let matches = match claim("aud") {
+                Some(Value::String(aud)) => self.audiences.contains(aud),
+                Some(Value::Array(auds)) => auds.iter().any(|aud| {
+                    aud.as_str()
+                        .is_some_and(|aud| self.audiences.iter().any(|expected| expected == aud))
+                }),
+                _ => false,
+            };
+
+            if !matches {
+                violations.push(violation(
+                    "audiences",
+                    format!(
+                        "the `aud` claim doesn't include any of {}",
+                        self.audiences.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(subject) = &self.subject {
+            if claim("sub").and_then(Value::as_str) != Some(subject.as_str()) {
+                violations.push(violation(
+                    "subject",
+                    format!("the `sub` claim isn't {}", subject),
+                ));
+            }
+        }
+
+        if let Some(exp) = timestamp("exp").filter(|_| validation.validate_exp) {
+            if exp < now - leeway {
+                violations.push(violation("exp", "the token has expired".to_string()));
+            }
+        }
+
+        if let Some(nbf) = timestamp("nbf") {
+            if nbf > now + leeway {
+                violations.push(violation("nbf", "the token isn't valid yet".to_string()));
+            }
+        }
+
+        if let Some(max_lifetime) = self.max_lifetime {
+            match (timestamp("iat"), timestamp("exp")) {
+                (Some(iat), Some(exp)) if exp - iat > max_lifetime as f64 => {
+                    violations.push(violation(
+                        "max_lifetime",
+                        format!(
+                            "the token is valid for {} seconds, more than the {} allowed",
+                            exp - iat,
+                            max_lifetime
+                        ),
+                    ))
+                }
+                (Some(_), Some(_)) => (),
+                _ => violations.push(violation(
+                    "max_lifetime",
+                    "the token needs both `iat` and `exp` claims".to_string(),
+                )),
+            }
+        }
+
+        for (name, rule) in &self.claims {
+            let value = match claim(name) {
+                Some(value) => value,
+                None if rule.required => {
+                    violations.push(violation(
+                        &format!("claims.{}.required", name),
+                        format!("the `{}` claim is missing", name),
+                    ));
+                    continue;
+                }
+                None => continue,
+            };
+
+            if let Some(claim_type) = rule.claim_type {
+                if !claim_type.matches(value) {
+                    violations.push(violation(
+                        &format!("claims.{}.type", name),
+                        format!("the `{}` claim isn't {}", name, claim_type),
+                    ));
+                }
+            }
+
+            // Patterns were checked when the policy was read
+            if let Some(pattern) = rule.pattern.as_deref().and_then(|p| Regex::new(p).ok()) {
+                let matches = match value {
+                    Value::String(value) => pattern.is_match(value),
+                    value => pattern.is_match(&value.to_string()),
+                };
+
+                if !matches {
+                    violations.push(violation(
+                        &format!("claims.{}.pattern", name),
+                        format!("the `{}` claim doesn't match {}", name, pattern),
+                    ));
+                }
+            }
+        }
+
+        violations
+    }
+}
@@ -0,0 +1,32 @@
+use crate::payload::{parse_claim_path, PathSegment};
+use serde_json::Value;
+
+/// Parses a query like `payload.realm_access.roles[0]`, which is written like a claim path.
+/// It can start with `$.` as in JSONPath or `.` as in jq, and `.` or `$` alone is the
+/// whole document.
+pub fn parse_query(query: &str) -> Result<Vec<PathSegment>, String> {
+    let path = query.strip_prefix('$').unwrap_or(query);
+    let path = path.strip_prefix('.').unwrap_or(path);
+
+    if path.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match parse_claim_path(path) {
+        Ok(segments) if !segments.contains(&PathSegment::Append) => Ok(segments),
+        _ => Err(format!("{} isn't a valid query", query)),
+    }
+}
+
+/// Looks up the value `query` leads to in `document`, or None when there's nothing there
+pub fn query<'a>(document: &'a Value, query: &str) -> Result<Option<&'a Value>, String> {
+    let segments = parse_query(query)?;
+
+    Ok(segments
+        .iter()
+        .try_fold(document, |value, segment| match segment {
+            PathSegment::Key(name) => value.get(name),
+            PathSegment::Index(index) => value.get(index),
+            PathSegment::Append => None,
+        }))
+}
@@ -0,0 +1,159 @@
+use crate::error::{Error, Result};
+use crate::jwks::JwkSet;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where to fetch the JWK Set used to verify a token from
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwksSource {
+    /// The URL of the set itself
+    Url(String),
+    /// An OpenID Connect issuer, whose discovery document has the `jwks_uri` of the set
+    Issuer(String),
+}
+
+// A fetched document, as it's kept in the cache
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedDocument {
+    url: String,
+    expires_at: u64,
+    body: Value,
+}
+
+fn fetch_error(url: &str, reason: impl std::fmt::Display) -> Error {
+    Error::Io(format!("unable to fetch {}: {}", url, reason))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// How long a response may be cached for according to its Cache-Control and Age headers.
+// Responses without a max-age, or which mustn't be stored, aren't cached at all.
+fn cache_lifetime(cache_control: Option<&str>, age: Option<&str>) -> Option<u64> {
+    let mut max_age = None;
+
+    for directive in cache_control?.split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+
+        match directive.split_once('=') {
+            Some(("max-age", seconds)) => max_age = seconds.trim_matches('"').parse().ok(),
+            None if directive == "no-store" || directive == "no-cache" => return None,
+            _ => (),
+        }
+    }
+
+    let age = age.and_then(|age| age.trim().parse().ok()).unwrap_or(0);
+
+    max_age
+        .and_then(|max_age: u64| max_age.checked_sub(age))
+        .filter(|lifetime| *lifetime > 0)
+}
+
+fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    cache_dir.join(format!(
+        "{}.json",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(url.as_bytes()))
+    ))
+}
+
+fn read_cache(cache_dir: &Path, url: &str) -> Option<Value> {
+    let contents = fs::read(cache_path(cache_dir, url)).ok()?;
+    let cached: CachedDocument = serde_json::from_slice(&contents).ok()?;
+
+    if cached.url == url && cached.expires_at > now() {
+        Some(cached.body)
+    } else {
+        None
+    }
+}
+
+// Failing to cache a document isn't a reason to fail fetching it, so errors are ignored
+fn write_cache(cache_dir: &Path, url: &str, body: &Value, lifetime: u64) {
+    let cached = CachedDocument {
+        url: url.to_string(),
+        expires_at: now() + lifetime,
+        body: body.clone(),
+    };
+
+    if let Ok(contents) = serde_json::to_vec(&cached) {
+        let _ = fs::create_dir_all(cache_dir)
+            .and_then(|_| fs::write(cache_path(cache_dir, url), contents));
+    }
+}
+
+// Fetches a JSON document, from the cache when it has a fresh copy
+fn fetch_json(url: &str, cache_dir: Option<&Path>) -> Result<Value> {
+    if let Some(body) = cache_dir.and_then(|cache_dir| read_cache(cache_dir, url)) {
+        return Ok(body);
+    }
+
+    let response = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .get(url)
+        .set("Accept", "application/json")
+        .call()
+        .map_err(|err| fetch_error(url, err))?;
+    let lifetime = cache_lifetime(response.header("Cache-Control"), response.header("Age"));
+    let body: Value = response
+        .into_string()
+        .map_err(|err| fetch_error(url, err))
+        .and_then(|body| {
+            serde_json::from_str(&body)
+                .map_err(|err| fetch_error(url, format!("the response isn't JSON ({})", err)))
+        })?;
+
+    if let (Some(cache_dir), Some(lifetime)) = (cache_dir, lifetime) {
+        write_cache(cache_dir, url, &body, lifetime);
+    }
+
+    Ok(body)
+}
+
+// Follows the discovery document of an OpenID Connect issuer to its JWK Set
+fn jwks_uri(issuer: &str, cache_dir: Option<&Path>) -> Result<String> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+    let configuration = fetch_json(&url, cache_dir)?;
+
+    // The issuer is compared as the spec requires, to stop one issuer impersonating another
+    if configuration["issuer"]
+        .as_str()
+        .map(|iss| iss.trim_end_matches('/'))
+        != Some(issuer.trim_end_matches('/'))
+    {
+        return Err(fetch_error(
+            &url,
+            "the discovery document is for a different issuer",
+        ));
+    }
+
+    match configuration["jwks_uri"].as_str() {
+        Some(jwks_uri) => Ok(jwks_uri.to_string()),
+        None => Err(fetch_error(&url, "the discovery document has no jwks_uri")),
+    }
+}
+
+/// Fetches a JWK Set. Documents are cached in `cache_dir` for as long as
+/// their Cache-Control header allows, and not at all without one.
+pub fn fetch_jwks(source: &JwksSource, cache_dir: Option<&Path>) -> Result<JwkSet> {
+    let url = match source {
+        JwksSource::Url(url) => url.clone(),
+        JwksSource::Issuer(issuer) => jwks_uri(issuer, cache_dir)?,
+    };
+
+    serde_json::from_value(fetch_json(&url, cache_dir)?)
+        .map_err(|err| fetch_error(&url, format!("the response isn't a JWK Set ({})", err)))
+}
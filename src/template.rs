@@ -0,0 +1,111 @@
+use crate::error::{Error, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::collections::BTreeMap;
+use std::env;
+
+fn template_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+// A random version 4 UUID
+fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+
+    OsRng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// What a placeholder is replaced with: a helper's result or the value of a variable
+fn placeholder_value(name: &str, vars: &BTreeMap<String, String>, now: i64) -> Result<String> {
+    let offset = |duration: &str| {
+        parse_duration::parse(duration)
+            .map(|duration| duration.as_secs() as i64)
+            .map_err(|_| template_error(format!("${{{}}} doesn't have a valid duration", name)))
+    };
+
+    if let Some(variable) = name.strip_prefix("env:") {
+        return env::var(variable).map_err(|_| {
+            template_error(format!(
+                "${{{}}} is used in the template, but {} isn't set",
+                name, variable
+            ))
+        });
+    }
+
+    match name {
+        "now" => Ok(now.to_string()),
+        "uuid" => Ok(uuid()),
+        _ => match (name.strip_prefix("now+"), name.strip_prefix("now-")) {
+            (Some(duration), _) => Ok((now + offset(duration)?).to_string()),
+            (_, Some(duration)) => Ok((now - offset(duration)?).to_string()),
+            _ => vars.get(name).cloned().ok_or_else(|| {
+                template_error(format!(
+                    "${{{}}} is used in the template, but there's no --var for it",
+                    name
+                ))
+            }),
+        },
+    }
+}
+
+/// Fills in the `${...}` placeholders of a claims template with `vars`, or with helpers:
+/// `${now}` is the UNIX timestamp `now`, `${now+1h}` or `${now-5 min}` one offset from it,
+/// `${uuid}` a random UUID and `${env:NAME}` an environment variable. Within JSON strings,
+/// values are escaped; elsewhere they're put as they are, so `${now}` can be a number.
+pub fn render_template(
+    template: &str,
+    vars: &BTreeMap<String, String>,
+    now: i64,
+) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut rest = template;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("${") && !escaped {
+            let end = rest.find('}').ok_or_else(|| {
+                template_error("a placeholder in the template isn't closed".to_string())
+            })?;
+            let value = placeholder_value(&rest[2..end], vars, now)?;
+
+            match in_string {
+                true => {
+                    let quoted = serde_json::to_string(&value)?;
+
+                    rendered.push_str(&quoted[1..quoted.len() - 1]);
+                }
+                false => rendered.push_str(&value),
+            }
+
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        if escaped {
+            escaped = false;
+        } else if in_string && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_string = !in_string;
+        }
+
+        rendered.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    Ok(rendered)
+}
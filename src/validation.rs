@@ -0,0 +1,166 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Result, ValidationError};
+use crate::payload::Payload;
+use chrono::Utc;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashSet};
+
+/// Which checks to run on a token after its signature has been verified
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validation {
+    /// The algorithms the token may be signed with
+    pub algorithms: Vec<SupportedAlgorithms>,
+    /// The number of seconds of clock skew allowed when checking `exp`, `nbf` and `iat`
+    pub leeway: u64,
+    pub validate_exp: bool,
+    pub validate_nbf: bool,
+    /// Claims which have to be present in the token
+    pub required_spec_claims: HashSet<String>,
+    /// The issuers the `iss` claim may be one of
+    pub iss: Option<HashSet<String>>,
+    /// The audiences the `aud` claim has to include at least one of
+    pub aud: Option<HashSet<String>>,
+    /// The value the `sub` claim must have
+    pub sub: Option<String>,
+    /// The number of seconds since `iat` after which the token is treated as expired
+    pub max_age: Option<u64>,
+}
+
+impl Validation {
+    /// Requires and validates `exp`, accepting only `alg`
+    pub fn new(alg: SupportedAlgorithms) -> Validation {
+        Validation {
+            algorithms: vec![alg],
+            leeway: 60,
+            validate_exp: true,
+            validate_nbf: false,
+            required_spec_claims: vec!["exp".to_string()].into_iter().collect(),
+            iss: None,
+            aud: None,
+            sub: None,
+            max_age: None,
+        }
+    }
+
+    /// Only accepts tokens whose `iss` is one of `items`
+    pub fn set_issuer<T: ToString>(&mut self, items: &[T]) {
+        self.iss = Some(items.iter().map(ToString::to_string).collect());
+        self.required_spec_claims.insert("iss".to_string());
+    }
+
+    /// Only accepts tokens whose `aud` includes one of `items`
+    pub fn set_audience<T: ToString>(&mut self, items: &[T]) {
+        self.aud = Some(items.iter().map(ToString::to_string).collect());
+        self.required_spec_claims.insert("aud".to_string());
+    }
+
+    /// Only accepts tokens whose `sub` is `sub`
+    pub fn set_subject(&mut self, sub: &str) {
+        self.sub = Some(sub.to_string());
+        self.required_spec_claims.insert("sub".to_string());
+    }
+
+    /// Only accepts tokens issued at most `seconds` ago, according to `iat`
+    pub fn set_max_age(&mut self, seconds: u64) {
+        self.max_age = Some(seconds);
+        self.required_spec_claims.insert("iat".to_string());
+    }
+
+    /// The claims which are checked, in alphabetical order
+    pub fn checked_claims(&self) -> Vec<String> {
+        let checks = [
+            ("exp", self.validate_exp),
+            ("nbf", self.validate_nbf),
+            ("iss", self.iss.is_some()),
+            ("aud", self.aud.is_some()),
+            ("sub", self.sub.is_some()),
+            ("iat", self.max_age.is_some()),
+        ];
+        let claims: BTreeSet<String> = checks
+            .iter()
+            .filter(|(_, checked)| *checked)
+            .map(|(claim, _)| claim.to_string())
+            .chain(self.required_spec_claims.iter().cloned())
+            .collect();
+
+        claims.into_iter().collect()
+    }
+}
+
+// Claims which only count as present when they're a NumericDate
+const TIMESTAMP_CLAIMS: [&str; 3] = ["exp", "nbf", "iat"];
+
+/// The seconds since the epoch of a NumericDate, which can have a fraction
+pub(crate) fn numeric_date(value: &Value) -> Option<f64> {
+    value.as_f64().filter(|seconds| seconds.is_finite())
+}
+
+/// Checks the claims of a token against `validation`
+pub fn validate(claims: &Payload, validation: &Validation) -> Result<()> {
+    for claim in &validation.required_spec_claims {
+        let present = match claims.0.get(claim) {
+            Some(value) if TIMESTAMP_CLAIMS.contains(&claim.as_str()) => {
+                numeric_date(value).is_some()
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if !present {
+            return Err(ValidationError::MissingClaim(claim.clone()).into());
+        }
+    }
+
+    // Dates are compared as floats, so fractions count and nothing can overflow
+    let now = Utc::now().timestamp() as f64;
+    let leeway = validation.leeway as f64;
+    let timestamp = |claim: &str| claims.0.get(claim).and_then(numeric_date);
+
+    if let Some(exp) = timestamp("exp") {
+        if validation.validate_exp && exp < now - leeway {
+            return Err(ValidationError::Expired.into());
+        }
+    }
+
+    if let Some(nbf) = timestamp("nbf") {
+        if validation.validate_nbf && nbf > now + leeway {
+            return Err(ValidationError::NotYetValid.into());
+        }
+    }
+
+    if let (Some(iat), Some(max_age)) = (timestamp("iat"), validation.max_age) {
+        if iat + (max_age as f64) < now - leeway {
+            return Err(ValidationError::TooOld(max_age).into());
+        }
+    }
+
+    if let Some(issuers) = &validation.iss {
+        match claims.0.get("iss") {
+            Some(Value::String(iss)) if issuers.contains(iss) => (),
+            _ => return Err(ValidationError::Issuer.into()),
+        }
+    }
+
+    if let Some(audiences) = &validation.aud {
+        // `aud` is either a single audience or an array of them
+        let matches = match claims.0.get("aud") {
+            Some(Value::String(aud)) => audiences.contains(aud),
+            Some(Value::Array(auds)) => auds
+                .iter()
+                .any(|aud| aud.as_str().is_some_and(|aud| audiences.contains(aud))),
+            _ => false,
+        };
+
+        if !matches {
+            return Err(ValidationError::Audience.into());
+        }
+    }
+
+    if let Some(sub) = &validation.sub {
+        if claims.0.get("sub").and_then(Value::as_str) != Some(sub.as_str()) {
+            return Err(ValidationError::Subject.into());
+        }
+    }
+
+    Ok(())
+}
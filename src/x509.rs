@@ -0,0 +1,286 @@
+use crate::algorithm::SupportedAlgorithms;
+use crate::error::{Error, Result};
+use crate::jwk::key_jwk;
+use crate::jws::Header;
+use crate::keys::{decoding_key, DecodingKey, KeyFormat};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use chrono::{TimeZone, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs;
+use x509_cert::der::oid::ObjectIdentifier;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::{BasicConstraints, KeyUsage};
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+// The certificate signature algorithms which match a JWS algorithm
+const SIGNATURE_ALGORITHMS: [(&str, SupportedAlgorithms); 7] = [
+    ("1.2.840.113549.1.1.11", SupportedAlgorithms::RS256),
+    ("1.2.840.113549.1.1.12", SupportedAlgorithms::RS384),
+    ("1.2.840.113549.1.1.13", SupportedAlgorithms::RS512),
+    ("1.2.840.10045.4.3.2", SupportedAlgorithms::ES256),
+    ("1.2.840.10045.4.3.3", SupportedAlgorithms::ES384),
+    ("1.2.840.10045.4.3.4", SupportedAlgorithms::ES512),
+    ("1.3.101.112", SupportedAlgorithms::EdDSA),
+];
+
+/// A certificate of the chain a token was verified with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainCertificate {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+fn invalid_certificate<E: std::fmt::Display>(err: E) -> Error {
+    Error::Malformed(format!("the certificate is invalid: {}", err))
+}
+
+fn parse_certificate(der: &[u8]) -> Result<Certificate> {
+    Certificate::from_der(der).map_err(invalid_certificate)
+}
+
+fn invalid_chain(message: String) -> Error {
+    Error::Crypto(format!("the certificate chain is invalid: {}", message))
+}
+
+fn timestamp(time: Time) -> i64 {
+    time.to_unix_duration().as_secs() as i64
+}
+
+fn rfc3339(time: Time) -> String {
+    Utc.timestamp_opt(timestamp(time), 0)
+        .single()
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| time.to_string())
+}
+
+impl ChainCertificate {
+    fn new(cert: &Certificate) -> Self {
+        let validity = &cert.tbs_certificate.validity;
+
+        ChainCertificate {
+            subject: cert.tbs_certificate.subject.to_string(),
+            issuer: cert.tbs_certificate.issuer.to_string(),
+            not_before: rfc3339(validity.not_before),
+            not_after: rfc3339(validity.not_after),
+        }
+    }
+}
+
+/// Reads a chain of PEM certificates from a file, returning them as DER in the
+/// order they're in, which should be the leaf first
+pub fn read_cert_chain(path: &str) -> Result<Vec<Vec<u8>>> {
+    let pem =
+        fs::read(path).map_err(|err| Error::Io(format!("unable to read {}: {}", path, err)))?;
+    let chain = Certificate::load_pem_chain(&pem).map_err(invalid_certificate)?;
+
+    if chain.is_empty() {
+        return Err(Error::Malformed(format!("{} has no certificates", path)));
+    }
+
+    chain
+        .iter()
+        .map(|cert| cert.to_der().map_err(invalid_certificate))
+        .collect()
+}
+
+fn public_key_jwk(cert: &Certificate) -> Result<Value> {
+    let spki = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(invalid_certificate)?;
+
+    key_jwk(&spki, KeyFormat::DER)
+}
+
+/// The public key of a DER certificate, as a JWK
+pub fn certificate_jwk(der: &[u8]) -> Result<Value> {
+    public_key_jwk(&parse_certificate(der)?)
+}
+
+// The key of a DER certificate, ready to verify tokens signed with `alg`
+pub(crate) fn certificate_key(der: &[u8], alg: SupportedAlgorithms) -> Result<DecodingKey> {
+    decoding_key(
+        alg,
+        &serde_json::to_vec(&certificate_jwk(der)?)?,
+        KeyFormat::JWK,
+    )
+}
+
+// Decodes the standard base64 certificates of an `x5c` header or JWK parameter into DER
+pub(crate) fn decode_x5c(x5c: &[String]) -> Result<Vec<Vec<u8>>> {
+    x5c.iter().map(|cert| Ok(STANDARD.decode(cert)?)).collect()
+}
+
+// Checks that `issuer` signed `cert`
+fn check_signature(cert: &Certificate, issuer: &Certificate) -> Result<()> {
+    let oid = cert.signature_algorithm.oid;
+    let alg = SIGNATURE_ALGORITHMS
+        .iter()
+        .find(|(algorithm, _)| ObjectIdentifier::new_unwrap(algorithm) == oid)
+        .map(|(_, alg)| *alg)
+        .ok_or_else(|| invalid_chain(format!("{} is signed with an unsupported algorithm", oid)))?;
+    let signature = cert
+        .signature
+        .as_bytes()
+        .ok_or_else(|| invalid_chain("a signature isn't a whole number of bytes".to_string()))?;
+    // Certificates hold ECDSA signatures as DER, where JWS has the two integers side by side
+    let signature = match alg {
+        SupportedAlgorithms::ES256 => p256::ecdsa::Signature::from_der(signature)
+            .map(|signature| signature.to_bytes().to_vec()),
+        SupportedAlgorithms::ES384 => p384::ecdsa::Signature::from_der(signature)
+            .map(|signature| signature.to_bytes().to_vec()),
+        SupportedAlgorithms::ES512 => p521::ecdsa::Signature::from_der(signature)
+            .map(|signature| signature.to_bytes().to_vec()),
+        _ => Ok(signature.to_vec()),
+    }
+    .map_err(|_| invalid_chain("an ECDSA signature is malformed".to_string()))?;
+    let tbs = cert.tbs_certificate.to_der().map_err(invalid_certificate)?;
+    let key = decoding_key(
+        alg,
+        &serde_json::to_vec(&public_key_jwk(issuer)?)?,
+        KeyFormat::JWK,
+    )?;
+
+    match key.verify(&URL_SAFE_NO_PAD.encode(signature), &tbs, alg)? {
+        true => Ok(()),
+        false => Err(invalid_chain(format!(
+            "the signature of {} doesn't match {}",
+            cert.tbs_certificate.subject, issuer.tbs_certificate.subject
+        ))),
+    }
+}
+
+// Checks that a certificate is valid at `now`
+fn check_validity(cert: &Certificate, now: i64) -> Result<()> {
+    let validity = &cert.tbs_certificate.validity;
+    let subject = &cert.tbs_certificate.subject;
+
+    if now < timestamp(validity.not_before) {
+        return Err(invalid_chain(format!(
+            "{} isn't valid until {}",
+            subject,
+            rfc3339(validity.not_before)
+        )));
+    }
+
+    if now > timestamp(validity.not_after) {
+        return Err(invalid_chain(format!(
+            "{} expired at {}",
+            subject,
+            rfc3339(validity.not_after)
+        )));
+    }
+
+    Ok(())
+}
+
+// Checks that a certificate may issue other certificates. A key usage is only
+// required to include certificate signing when there is one.
+fn check_issuer(cert: &Certificate) -> Result<()> {
+    let tbs = &cert.tbs_certificate;
+    let is_ca =
+        matches!(tbs.get::<BasicConstraints>(), Ok(Some((_, constraints))) if constraints.ca);
+    let may_sign = match tbs.get::<KeyUsage>() {
+        Ok(Some((_, usage))) => usage.key_cert_sign(),
+        Ok(None) => true,
+        Err(_) => false,
+    };
+
+    match is_ca && may_sign {
+        true => Ok(()),
+        false => Err(invalid_chain(format!(
+            "{} isn't allowed to issue certificates",
+            tbs.subject
+        ))),
+    }
+}
+
+/// Validates a chain of DER certificates, leaf first, at the UNIX timestamp `now`. Each
+/// certificate has to be issued by the next one, and the last by one of the trust anchors
+/// unless it's one itself. The leaf has to be allowed to make digital signatures. Returns
+/// the chain, ending with the trust anchor.
+pub fn validate_cert_chain(
+    chain: &[Vec<u8>],
+    trust_anchors: &[Vec<u8>],
+    now: i64,
+) -> Result<Vec<ChainCertificate>> {
+    let chain = chain
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+    let anchors = trust_anchors
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| invalid_chain("it has no certificates".to_string()))?;
+
+    if let Ok(Some((_, usage))) = leaf.tbs_certificate.get::<KeyUsage>() {
+        if !usage.digital_signature() {
+            return Err(invalid_chain(format!(
+                "{} isn't allowed to sign tokens",
+                leaf.tbs_certificate.subject
+            )));
+        }
+    }
+
+    for pair in chain.windows(2) {
+        if pair[0].tbs_certificate.issuer != pair[1].tbs_certificate.subject {
+            return Err(invalid_chain(format!(
+                "{} isn't issued by {}",
+                pair[0].tbs_certificate.subject, pair[1].tbs_certificate.subject
+            )));
+        }
+
+        check_issuer(&pair[1])?;
+        check_signature(&pair[0], &pair[1])?;
+    }
+
+    let last = chain.last().unwrap_or(leaf);
+    let mut path = chain.clone();
+
+    if !anchors.contains(last) {
+        let anchor = anchors
+            .iter()
+            .filter(|anchor| anchor.tbs_certificate.subject == last.tbs_certificate.issuer)
+            .find(|anchor| {
+                check_issuer(anchor)
+                    .and_then(|_| check_signature(last, anchor))
+                    .is_ok()
+            })
+            .ok_or_else(|| {
+                invalid_chain(format!(
+                    "{} isn't issued by a trust anchor",
+                    last.tbs_certificate.subject
+                ))
+            })?;
+
+        path.push(anchor.clone());
+    }
+
+    for cert in &path {
+        check_validity(cert, now)?;
+    }
+
+    Ok(path.iter().map(ChainCertificate::new).collect())
+}
+
+/// Puts a chain of DER certificates in the `x5c` header parameter, and the SHA-1
+/// and SHA-256 thumbprints of its leaf in `x5t` and `x5t#S256`
+pub fn add_cert_chain(header: &mut Header, chain: &[Vec<u8>]) {
+    header.x5c = Some(chain.iter().map(|der| STANDARD.encode(der)).collect());
+
+    if let Some(leaf) = chain.first() {
+        header.x5t = Some(URL_SAFE_NO_PAD.encode(Sha1::digest(leaf)));
+        header.x5t_s256 = Some(URL_SAFE_NO_PAD.encode(Sha256::digest(leaf)));
+    }
+}